@@ -1,57 +1,330 @@
 use std::collections::HashMap;
-use actix::{Actor, Addr, Message, Recipient};
-use actix::dev::channel::AddressSender;
-use actix_web::Handler;
-use serde_json::json;
+use std::sync::Arc;
+use actix::{fut, Actor, ActorFutureExt, Addr, AsyncContext, Context, Message, Recipient, ResponseActFuture, ResponseFuture, WrapFuture};
+use futures_util::future::{self, LocalBoxFuture, Shared};
+use futures_util::FutureExt;
+use serde_json::{json, Value};
+use crate::app_manager::AppManager;
+use crate::cache::CacheManager;
+use crate::channel_managers::{self, presence_channel_manager::PresenceMember};
 use crate::log::Log;
-use crate::message::PusherApiMessage;
-use crate::namespace::{Namespace, BroadcastMessage, Channel};
-use crate::WS;
-use crate::ws_message::{OnPusherMessage};
+use crate::message::{PusherApiMessage, PusherApiMessageInfo};
+use crate::adapter::{ClusterMessage, ClusterQuery, ClusterReply, HorizontalAdapter};
+use crate::namespace::{
+    BroadcastMessage, Channel, ChannelInfo, ChannelMembers, Departures, GetChannelMembers, GetOccupiedChannels,
+    GetPresenceMembers, GetSockets, Namespace,
+};
+use crate::pusher_error::PusherError;
+use crate::webhook::webhook_sender::{QueueWebhook, WebhookSender};
+use crate::webhook::WebhookEvent;
+use crate::ws_message::OnPusherMessage;
 
 pub struct LocalAdapter {
     pub namespaces: HashMap<String, Addr<Namespace>>,
+    pub app_manager: Arc<AppManager>,
+    /// Server-wide connection cap across all apps.
+    pub max_connections: Option<u64>,
+    pub connections: u64,
+    /// Connections per app; an app's namespace lives while it has any.
+    pub app_connections: HashMap<String, u64>,
+    pub webhook_sender: Addr<WebhookSender>,
+    /// Holds the last event of each cache channel.
+    pub cache: Arc<dyn CacheManager>,
+    /// Cache writes still in flight by key, so reads of that key wait for them.
+    pub cache_writes: HashMap<String, Shared<LocalBoxFuture<'static, ()>>>,
+    /// Reaches the other nodes; `None` when this node runs alone.
+    pub horizontal: Option<Box<dyn HorizontalAdapter>>,
+}
+
+impl LocalAdapter {
+    pub fn new(app_manager: Arc<AppManager>, max_connections: Option<u64>, webhook_sender: Addr<WebhookSender>, cache: Arc<dyn CacheManager>) -> Self {
+        LocalAdapter {
+            namespaces: HashMap::new(),
+            app_manager,
+            max_connections,
+            connections: 0,
+            app_connections: HashMap::new(),
+            webhook_sender,
+            cache,
+            cache_writes: HashMap::new(),
+            horizontal: None,
+        }
+    }
+
+    /// A channel's membership across the cluster.
+    fn channel_members(&mut self, app_id: &str, channel: &str) -> LocalBoxFuture<'static, ChannelMembers> {
+        let namespace = self.namespace(app_id).cloned();
+        let remote = self.cluster_request(app_id, ClusterQuery::ChannelMembers {
+            channel: channel.to_string(),
+        });
+        let channel = channel.to_string();
+        Box::pin(async move {
+            let mut members = match namespace {
+                Some(namespace) => namespace.send(GetChannelMembers { channel }).await.unwrap_or_default(),
+                None => ChannelMembers::default(),
+            };
+            members.merge(merged_members(remote.await));
+            members
+        })
+    }
+
+    /// Asks the other nodes about an app; resolves to no replies when running alone.
+    fn cluster_request(&self, app_id: &str, query: ClusterQuery) -> LocalBoxFuture<'static, Vec<ClusterReply>> {
+        match &self.horizontal {
+            Some(horizontal) => horizontal.request(app_id, query),
+            None => Box::pin(future::ready(vec![])),
+        }
+    }
+
+    fn publish(&self, message: ClusterMessage) {
+        if let Some(horizontal) = &self.horizontal {
+            horizontal.publish(message);
+        }
+    }
+
+    /// Sends an API event to this node's subscribers of its channels.
+    fn broadcast(&mut self, app_id: &str, message: &PusherApiMessage) {
+        let Some(namespace) = self.namespace(app_id) else {
+            return;
+        };
+        for ch in message.channels() {
+            let msg = PusherApiMessage {
+                name: message.name.clone(),
+                data: message.data.clone(),
+                channel: Some(ch.clone()),
+                channels: Some(vec![ch.clone()]),
+                socket_id: message.socket_id.clone(),
+                info: None,
+            };
+            Log::websocket_title(format!("Broadcasting {:?} to channel {} in app {}", msg.name, ch, app_id).as_str());
+            namespace.do_send(BroadcastMessage(msg));
+        }
+    }
+
+    /// Stores the event as the last one of its cache channels. The writes run
+    /// in the background; reads of the same channel wait for them to land.
+    fn cache_event(&mut self, app_id: &str, message: &PusherApiMessage, ctx: &mut Context<Self>) {
+        let entries: Vec<(String, String)> = message
+            .channels()
+            .into_iter()
+            .filter(|channel| channel_managers::is_cache_channel(channel))
+            .map(|channel| (cache_key(app_id, &channel), message.frame(&channel).to_string()))
+            .collect();
+        if entries.is_empty() {
+            return;
+        }
+        let Ok(app) = self.app_manager.find_by_id(app_id) else {
+            return;
+        };
+        let ttl = app.cache_ttl();
+        for (key, frame) in entries {
+            let cache = self.cache.clone();
+            let write_key = key.clone();
+            let write = async move { cache.set(&write_key, frame, ttl).await }.boxed_local().shared();
+            self.cache_writes.insert(key.clone(), write.clone());
+            let done = write.clone();
+            ctx.spawn(done.into_actor(self).map(move |_, act, _| {
+                // A later write of the same key replaced this one and is still pending.
+                if act.cache_writes.get(&key).is_some_and(|pending| pending.ptr_eq(&write)) {
+                    act.cache_writes.remove(&key);
+                }
+            }));
+        }
+    }
+
+    fn send_to_channel(&mut self, app_id: &str, channel: String, message: Value, except: Option<String>) {
+        if let Some(namespace) = self.namespace(app_id) {
+            namespace.do_send(crate::namespace::SendToChannel {
+                channel,
+                message,
+                except,
+            });
+        }
+    }
+
+    /// Sends a frame to the channel's subscribers on every node.
+    fn send_to_cluster_channel(&mut self, app_id: &str, channel: String, message: Value, except: Option<String>) {
+        self.send_to_channel(app_id, channel.clone(), message.clone(), except.clone());
+        self.publish(ClusterMessage::SendToChannel {
+            app_id: app_id.to_string(),
+            channel,
+            message,
+            except,
+        });
+    }
+
+    fn webhook(&self, app_id: &str, event: WebhookEvent) {
+        self.webhook_sender.do_send(QueueWebhook {
+            app_id: app_id.to_string(),
+            event,
+        });
+    }
+
+    /// Reports `channel_occupied` unless other nodes already had subscribers.
+    fn channel_occupied(&mut self, app_id: String, channel: String, ctx: &mut Context<Self>) {
+        let remote = self.cluster_request(&app_id, ClusterQuery::ChannelMembers {
+            channel: channel.clone(),
+        });
+        ctx.spawn(remote.into_actor(self).map(move |replies, act, _| {
+            if merged_members(replies).subscription_count == 0 {
+                act.webhook(&app_id, WebhookEvent::ChannelOccupied { channel });
+            }
+        }));
+    }
+
+    /// Announces the members and reports the channels that are gone from the
+    /// whole cluster, not just from this node, in the order they left.
+    fn report_departures(&mut self, app_id: String, departures: Departures, ctx: &mut Context<Self>) {
+        let mut channels: Vec<String> = departures.members_removed.iter()
+            .map(|(channel, _)| channel.clone())
+            .chain(departures.channels_vacated.iter().cloned())
+            .collect();
+        channels.sort();
+        channels.dedup();
+        let requests = future::join_all(channels.iter().map(|channel| self.cluster_request(&app_id, ClusterQuery::ChannelMembers {
+            channel: channel.clone(),
+        })));
+        let remote = async move {
+            channels.into_iter()
+                .zip(requests.await.into_iter().map(merged_members))
+                .collect::<HashMap<_, _>>()
+        };
+        ctx.spawn(remote.into_actor(self).map(move |remote, act, _| {
+            for (channel, user_id) in departures.members_removed {
+                if remote.get(&channel).is_some_and(|members| members.user_ids.contains(&user_id)) {
+                    continue;
+                }
+                act.send_to_cluster_channel(&app_id, channel.clone(), json!({
+                    "event": "pusher_internal:member_removed",
+                    "channel": channel,
+                    "data": {
+                        "user_id": user_id,
+                    },
+                }), None);
+                act.webhook(&app_id, WebhookEvent::MemberRemoved { channel, user_id });
+            }
+            for channel in departures.channels_vacated {
+                if remote.get(&channel).is_none_or(|members| members.subscription_count == 0) {
+                    act.webhook(&app_id, WebhookEvent::ChannelVacated { channel });
+                }
+            }
+        }));
+    }
+
+    /// The app's namespace, if it has connections on this node.
+    fn namespace(&self, app_id: &str) -> Option<&Addr<Namespace>> {
+        self.namespaces.get(app_id)
+    }
+
+    /// Counts a new connection of the app, starting its namespace on the first one.
+    fn open_namespace(&mut self, app_id: &str) -> Result<Addr<Namespace>, PusherError> {
+        let namespace = match self.namespaces.get(app_id) {
+            Some(namespace) => namespace.clone(),
+            None => {
+                let app = self.app_manager.find_by_id(app_id)?;
+                Log::websocket_title(format!("Opening namespace for app {}", app_id).as_str());
+                let namespace = Namespace::new(app).start();
+                self.namespaces.insert(app_id.to_string(), namespace.clone());
+                namespace
+            }
+        };
+        self.connections += 1;
+        *self.app_connections.entry(app_id.to_string()).or_default() += 1;
+        Ok(namespace)
+    }
+
+    /// Releases a connection counted by `open_namespace`, dropping the
+    /// namespace with the app's last one.
+    fn close_connection(&mut self, app_id: &str) {
+        self.connections = self.connections.saturating_sub(1);
+        let Some(count) = self.app_connections.get_mut(app_id) else {
+            return;
+        };
+        *count -= 1;
+        if *count == 0 {
+            Log::websocket_title(format!("Closing namespace for app {}", app_id).as_str());
+            self.app_connections.remove(app_id);
+            self.namespaces.remove(app_id);
+        }
+    }
+}
+
+fn cache_key(app_id: &str, channel: &str) -> String {
+    format!("cache_channel:{}:{}", app_id, channel)
+}
+
+/// Merges the `ChannelMembers` replies of the other nodes.
+fn merged_members(replies: Vec<ClusterReply>) -> ChannelMembers {
+    let mut members = ChannelMembers::default();
+    for reply in replies {
+        if let ClusterReply::ChannelMembers(remote_members) = reply {
+            members.merge(remote_members);
+        }
+    }
+    members
 }
 
 impl Actor for LocalAdapter {
     type Context = actix::Context<Self>;
-    
+
     fn started(&mut self, _: &mut Self::Context) {
-        println!("LocalAdapter started");
-        let namespace1 = Namespace {
-            channels: HashMap::new(),
-            users: HashMap::new(),
-            app_id: "app1".to_string(),
-            sockets: HashMap::new(),
-        }.start();
-        let namespace2 = Namespace {
-            channels: HashMap::new(),
-            users: HashMap::new(),
-            app_id: "app2".to_string(),
-            sockets: HashMap::new(),
-        }.start();
-        self.namespaces.insert("app1".to_string(), namespace1);
-        self.namespaces.insert("app2".to_string(), namespace2);
+        Log::info_title("LocalAdapter started");
     }
 }
 
 #[derive(Message)]
-#[rtype(result = "()")]
+#[rtype(result = "Result<(), PusherError>")]
 pub struct AddSocket {
     pub(crate) app_id: String,
     pub(crate) socket_id: String,
-    pub(crate) socket_addr: Addr<WS>,
+    pub(crate) socket_addr: Recipient<OnPusherMessage>,
 }
 
 impl actix::Handler<AddSocket> for LocalAdapter {
-    type Result = ();
+    type Result = ResponseActFuture<Self, Result<(), PusherError>>;
 
-    fn handle(&mut self, msg: AddSocket, _: &mut Self::Context) {
+    /// Registers a new connection with its app, enforcing the server-wide
+    /// cap here and the app's own `max_connections` in the namespace.
+    fn handle(&mut self, msg: AddSocket, _: &mut Self::Context) -> Self::Result {
         Log::websocket_title(format!("Adding socket {} to app {}", msg.socket_id, msg.app_id).as_str());
-        self.namespaces.get(&msg.app_id.clone()).unwrap().do_send(crate::namespace::AddSocket {
-            socket_id: msg.socket_id.clone(),
-            socket_addr: msg.socket_addr.clone(),
-        });
+        if self.max_connections.is_some_and(|max_connections| self.connections >= max_connections) {
+            return Box::pin(fut::ready(Err(PusherError::OverCapacity)));
+        }
+        // Reserve the slot now so concurrent connections can't overshoot the cap.
+        let namespace = match self.open_namespace(&msg.app_id) {
+            Ok(namespace) => namespace,
+            Err(error) => return Box::pin(fut::ready(Err(error))),
+        };
+        let app_id = msg.app_id.clone();
+        // Other nodes only need asking when the app caps its connections.
+        let app_is_capped = self.app_manager.find_by_id(&msg.app_id).is_ok_and(|app| app.max_connections.is_some());
+        let remote_counts = if app_is_capped {
+            self.cluster_request(&msg.app_id, ClusterQuery::SocketCount)
+        } else {
+            Box::pin(future::ready(vec![]))
+        };
+        let add_socket = async move {
+            let remote_connections = remote_counts
+                .await
+                .into_iter()
+                .map(|reply| match reply {
+                    ClusterReply::SocketCount(count) => count,
+                    _ => 0,
+                })
+                .sum();
+            namespace.send(crate::namespace::AddSocket {
+                socket_id: msg.socket_id,
+                socket_addr: msg.socket_addr,
+                remote_connections,
+            }).await
+        };
+        Box::pin(add_socket.into_actor(self).map(move |result, act, _| {
+            let result = result.unwrap_or(Err(PusherError::OverCapacity));
+            if result.is_err() {
+                act.close_connection(&app_id);
+            }
+            result
+        }))
     }
 }
 
@@ -65,35 +338,46 @@ pub struct SendMessage {
 impl actix::Handler<SendMessage> for LocalAdapter {
     type Result = ();
 
-    fn handle(&mut self, msg: SendMessage, _: &mut Self::Context) {
-        let namespace = self.namespaces.get(&msg.app_id.clone()).unwrap();
+    fn handle(&mut self, msg: SendMessage, ctx: &mut Self::Context) {
         Log::websocket_title(format!("Sending message to app {}", msg.app_id).as_str());
-        for ch in msg.message.channels.unwrap() {
-            let msg = PusherApiMessage {
-                name: msg.message.name.clone(),
-                data: msg.message.data.clone(),
-                channel: Some(ch.clone()),
-                channels: Some(vec![ch.clone()]),
-                socket_id: None,
-                info: None,
-            };
-            println!("Broadcasting message: {:?}", msg);
-            namespace.do_send(BroadcastMessage(msg));
-        }
+        self.cache_event(&msg.app_id, &msg.message, ctx);
+        self.broadcast(&msg.app_id, &msg.message);
+        self.publish(ClusterMessage::Broadcast {
+            app_id: msg.app_id,
+            message: msg.message,
+        });
+    }
+}
+
+/// Sends a ready-made Pusher frame to a channel's subscribers, e.g. a client event.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SendToChannel {
+    pub(crate) app_id: String,
+    pub(crate) channel: String,
+    pub(crate) message: Value,
+    pub(crate) except: Option<String>,
+}
+
+impl actix::Handler<SendToChannel> for LocalAdapter {
+    type Result = ();
+
+    fn handle(&mut self, msg: SendToChannel, _: &mut Self::Context) {
+        self.send_to_cluster_channel(&msg.app_id, msg.channel, msg.message, msg.except);
     }
 }
 
 #[derive(Message)]
-#[rtype(result = "Addr<Namespace>")]
+#[rtype(result = "Option<Addr<Namespace>>")]
 pub struct GetNamespace {
     pub(crate) app_id: String,
 }
 
 impl actix::Handler<GetNamespace> for LocalAdapter {
-    type Result = Addr<Namespace>;
+    type Result = Option<Addr<Namespace>>;
 
     fn handle(&mut self, msg: GetNamespace, _: &mut Self::Context) -> Self::Result {
-        self.namespaces.get(&msg.app_id.clone()).unwrap().clone()
+        self.namespace(&msg.app_id).cloned()
     }
 }
 
@@ -109,12 +393,84 @@ pub struct AddToChannel {
 impl actix::Handler<AddToChannel> for LocalAdapter {
     type Result = ();
 
-    fn handle(&mut self, msg: AddToChannel, _: &mut Self::Context) {
+    fn handle(&mut self, msg: AddToChannel, ctx: &mut Self::Context) {
         Log::websocket_title(format!("Adding socket {} to channel {} in app {}", msg.socket_id, msg.channel, msg.app_id).as_str());
-        self.namespaces.get(&msg.app_id.clone()).unwrap().do_send(crate::namespace::AddToChannel {
+        let Some(namespace) = self.namespace(&msg.app_id).cloned() else {
+            return;
+        };
+        let add_to_channel = namespace.send(crate::namespace::AddToChannel {
+            socket_id: msg.socket_id,
+            channel: msg.channel.clone(),
+        });
+        ctx.spawn(add_to_channel.into_actor(self).map(move |subscription_count, act, ctx| {
+            if subscription_count.is_ok_and(|count| count == 1) {
+                act.channel_occupied(msg.app_id, msg.channel, ctx);
+            }
+        }));
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Option<HashMap<String, Value>>")]
+pub struct JoinPresenceChannel {
+    pub(crate) app_id: String,
+    pub(crate) channel: String,
+    pub(crate) socket_id: String,
+    pub(crate) member: PresenceMember,
+}
+
+impl actix::Handler<JoinPresenceChannel> for LocalAdapter {
+    type Result = ResponseActFuture<Self, Option<HashMap<String, Value>>>;
+
+    /// Joins the presence channel and returns the cluster-wide roster,
+    /// announcing `member_added` when the user is new to the whole cluster.
+    fn handle(&mut self, msg: JoinPresenceChannel, _: &mut Self::Context) -> Self::Result {
+        Log::websocket_title(format!("Adding user {} to presence channel {} in app {}", msg.member.user_id, msg.channel, msg.app_id).as_str());
+        let Some(namespace) = self.namespace(&msg.app_id).cloned() else {
+            return Box::pin(fut::ready(None));
+        };
+        let remote = self.cluster_request(&msg.app_id, ClusterQuery::PresenceMembers {
+            channel: msg.channel.clone(),
+        });
+        let join = namespace.send(crate::namespace::JoinPresenceChannel {
             socket_id: msg.socket_id.clone(),
             channel: msg.channel.clone(),
+            member: msg.member.clone(),
         });
+        let joined = async move { (join.await, remote.await) };
+        Box::pin(joined.into_actor(self).map(move |(joined, replies), act, _| {
+            let joined = joined.ok()?;
+            let mut roster = HashMap::new();
+            for reply in replies {
+                if let ClusterReply::PresenceMembers(members) = reply {
+                    roster.extend(members);
+                }
+            }
+            let occupied_elsewhere = !roster.is_empty();
+            let is_new_user = joined.is_new_user && !roster.contains_key(&msg.member.user_id);
+            roster.extend(joined.roster);
+            if joined.is_first_subscriber && !occupied_elsewhere {
+                act.webhook(&msg.app_id, WebhookEvent::ChannelOccupied {
+                    channel: msg.channel.clone(),
+                });
+            }
+            if is_new_user {
+                let PresenceMember { user_id, user_info } = msg.member;
+                act.send_to_cluster_channel(&msg.app_id, msg.channel.clone(), json!({
+                    "event": "pusher_internal:member_added",
+                    "channel": msg.channel,
+                    "data": {
+                        "user_id": user_id,
+                        "user_info": user_info,
+                    },
+                }), Some(msg.socket_id));
+                act.webhook(&msg.app_id, WebhookEvent::MemberAdded {
+                    channel: msg.channel,
+                    user_id,
+                });
+            }
+            Some(roster)
+        }))
     }
 }
 
@@ -129,12 +485,20 @@ pub struct RemoveFromChannel {
 impl actix::Handler<RemoveFromChannel> for LocalAdapter {
     type Result = ();
 
-    fn handle(&mut self, msg: RemoveFromChannel, _: &mut Self::Context) {
+    fn handle(&mut self, msg: RemoveFromChannel, ctx: &mut Self::Context) {
         Log::websocket_title(format!("Removing socket {} from channel {} in app {}", msg.socket_id, msg.channel, msg.app_id).as_str());
-        self.namespaces.get(&msg.app_id.clone()).unwrap().do_send(crate::namespace::RemoveFromChannel {
-            socket_id: msg.socket_id.clone(),
-            channel: Channel::Ch(msg.channel.clone()),
+        let Some(namespace) = self.namespace(&msg.app_id).cloned() else {
+            return;
+        };
+        let remove = namespace.send(crate::namespace::RemoveFromChannel {
+            socket_id: msg.socket_id,
+            channel: Channel::Ch(msg.channel),
         });
+        ctx.spawn(remove.into_actor(self).map(move |departures, act, ctx| {
+            if let Ok(departures) = departures {
+                act.report_departures(msg.app_id, departures, ctx);
+            }
+        }));
     }
 }
 
@@ -143,17 +507,556 @@ impl actix::Handler<RemoveFromChannel> for LocalAdapter {
 pub struct RemoveSocket {
     pub(crate) app_id: String,
     pub(crate) socket_id: String,
-    pub(crate) socket_addr: Addr<WS>,
 }
 
 impl actix::Handler<RemoveSocket> for LocalAdapter {
     type Result = ();
 
-    fn handle(&mut self, msg: RemoveSocket, _: &mut Self::Context) {
+    fn handle(&mut self, msg: RemoveSocket, ctx: &mut Self::Context) {
         Log::websocket_title(format!("Removing socket {} from app {}", msg.socket_id, msg.app_id).as_str());
-        self.namespaces.get(&msg.app_id.clone()).unwrap().do_send(crate::namespace::RemoveSocket {
-            socket_id: msg.socket_id.clone(),
-            socket_addr: msg.socket_addr.clone()
+        let Some(namespace) = self.namespace(&msg.app_id).cloned() else {
+            return;
+        };
+        self.close_connection(&msg.app_id);
+        let remove = namespace.send(crate::namespace::RemoveSocket {
+            socket_id: msg.socket_id,
         });
+        ctx.spawn(remove.into_actor(self).map(move |departures, act, ctx| {
+            if let Ok(departures) = departures {
+                act.report_departures(msg.app_id, departures, ctx);
+            }
+        }));
+    }
+}
+#[derive(Message)]
+#[rtype(result = "Option<Value>")]
+pub struct GetCachedEvent {
+    pub(crate) app_id: String,
+    pub(crate) channel: String,
+}
+
+impl actix::Handler<GetCachedEvent> for LocalAdapter {
+    type Result = ResponseFuture<Option<Value>>;
+
+    /// Returns the last event of a cache channel, reporting a `cache_miss`
+    /// webhook when there is none.
+    fn handle(&mut self, msg: GetCachedEvent, _: &mut Self::Context) -> Self::Result {
+        let cache = self.cache.clone();
+        let key = cache_key(&msg.app_id, &msg.channel);
+        let pending_write = self.cache_writes.get(&key).cloned();
+        let webhook_sender = self.webhook_sender.clone();
+        Box::pin(async move {
+            if let Some(pending_write) = pending_write {
+                pending_write.await;
+            }
+            let cached = cache
+                .get(&key)
+                .await
+                .and_then(|cached| serde_json::from_str::<Value>(&cached).ok());
+            if cached.is_none() {
+                webhook_sender.do_send(QueueWebhook {
+                    app_id: msg.app_id,
+                    event: WebhookEvent::CacheMiss { channel: msg.channel },
+                });
+            }
+            cached
+        })
     }
-}
\ No newline at end of file
+}
+
+#[derive(Message)]
+#[rtype(result = "ChannelInfo")]
+pub struct GetChannelInfo {
+    pub(crate) app_id: String,
+    pub(crate) channel: String,
+    pub(crate) info: PusherApiMessageInfo,
+}
+
+impl actix::Handler<GetChannelInfo> for LocalAdapter {
+    type Result = ResponseFuture<ChannelInfo>;
+
+    fn handle(&mut self, msg: GetChannelInfo, _: &mut Self::Context) -> Self::Result {
+        let members = self.channel_members(&msg.app_id, &msg.channel);
+        Box::pin(async move { members.await.info(&msg.channel, msg.info) })
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "HashMap<String, ChannelInfo>")]
+pub struct GetChannels {
+    pub(crate) app_id: String,
+    pub(crate) prefix: Option<String>,
+    pub(crate) info: PusherApiMessageInfo,
+}
+
+impl actix::Handler<GetChannels> for LocalAdapter {
+    type Result = ResponseFuture<HashMap<String, ChannelInfo>>;
+
+    fn handle(&mut self, msg: GetChannels, _: &mut Self::Context) -> Self::Result {
+        let namespace = self.namespace(&msg.app_id).cloned();
+        let remote = self.cluster_request(&msg.app_id, ClusterQuery::OccupiedChannels {
+            prefix: msg.prefix.clone(),
+        });
+        Box::pin(async move {
+            let local = match namespace {
+                Some(namespace) => namespace.send(GetOccupiedChannels { prefix: msg.prefix }).await.unwrap_or_default(),
+                None => HashMap::new(),
+            };
+            let mut channels = local;
+            for reply in remote.await {
+                if let ClusterReply::OccupiedChannels(remote_channels) = reply {
+                    for (channel, members) in remote_channels {
+                        channels.entry(channel).or_default().merge(members);
+                    }
+                }
+            }
+            channels
+                .into_iter()
+                .map(|(channel, members)| {
+                    let info = members.info(&channel, msg.info);
+                    (channel, info)
+                })
+                .collect()
+        })
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Vec<String>")]
+pub struct GetChannelUsers {
+    pub(crate) app_id: String,
+    pub(crate) channel: String,
+}
+
+impl actix::Handler<GetChannelUsers> for LocalAdapter {
+    type Result = ResponseFuture<Vec<String>>;
+
+    fn handle(&mut self, msg: GetChannelUsers, _: &mut Self::Context) -> Self::Result {
+        let members = self.channel_members(&msg.app_id, &msg.channel);
+        Box::pin(async move { members.await.user_ids.into_iter().collect() })
+    }
+}
+
+impl actix::Handler<QueueWebhook> for LocalAdapter {
+    type Result = ();
+
+    fn handle(&mut self, msg: QueueWebhook, _: &mut Self::Context) {
+        self.webhook_sender.do_send(msg);
+    }
+}
+
+/// A broadcast relayed from another node, delivered to this node's sockets only.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RemoteMessage(pub ClusterMessage);
+
+impl actix::Handler<RemoteMessage> for LocalAdapter {
+    type Result = ();
+
+    fn handle(&mut self, msg: RemoteMessage, ctx: &mut Self::Context) {
+        match msg.0 {
+            ClusterMessage::Broadcast { app_id, message } => {
+                self.cache_event(&app_id, &message, ctx);
+                self.broadcast(&app_id, &message);
+            }
+            ClusterMessage::SendToChannel { app_id, channel, message, except } => {
+                self.send_to_channel(&app_id, channel, message, except)
+            }
+        }
+    }
+}
+
+/// Another node's question about this node's share of an app.
+#[derive(Message)]
+#[rtype(result = "ClusterReply")]
+pub struct LocalQuery {
+    pub(crate) app_id: String,
+    pub(crate) query: ClusterQuery,
+}
+
+impl actix::Handler<LocalQuery> for LocalAdapter {
+    type Result = ResponseFuture<ClusterReply>;
+
+    fn handle(&mut self, msg: LocalQuery, _: &mut Self::Context) -> Self::Result {
+        let namespace = self.namespace(&msg.app_id).cloned();
+        Box::pin(async move {
+            match msg.query {
+                ClusterQuery::ChannelMembers { channel } => ClusterReply::ChannelMembers(match namespace {
+                    Some(namespace) => namespace.send(GetChannelMembers { channel }).await.unwrap_or_default(),
+                    None => ChannelMembers::default(),
+                }),
+                ClusterQuery::PresenceMembers { channel } => ClusterReply::PresenceMembers(match namespace {
+                    Some(namespace) => namespace.send(GetPresenceMembers { channel }).await.unwrap_or_default(),
+                    None => HashMap::new(),
+                }),
+                ClusterQuery::OccupiedChannels { prefix } => ClusterReply::OccupiedChannels(match namespace {
+                    Some(namespace) => namespace.send(GetOccupiedChannels { prefix }).await.unwrap_or_default(),
+                    None => HashMap::new(),
+                }),
+                ClusterQuery::SocketCount => ClusterReply::SocketCount(match namespace {
+                    Some(namespace) => namespace.send(GetSockets).await.map_or(0, |sockets| sockets.len() as u64),
+                    None => 0,
+                }),
+            }
+        })
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetHorizontalAdapter(pub Box<dyn HorizontalAdapter>);
+
+impl actix::Handler<SetHorizontalAdapter> for LocalAdapter {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetHorizontalAdapter, _: &mut Self::Context) {
+        self.horizontal = Some(msg.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use crate::adapter::memory_adapter::MemoryCluster;
+    use crate::adapter::testing::*;
+    use crate::adapter::REQUEST_TIMEOUT;
+    use crate::app::{AppConfig, WebhookConfig};
+    use crate::namespace::GetOccupiedChannels;
+
+    /// A cache whose writes take a while to land.
+    #[derive(Default)]
+    struct SlowCache {
+        entries: Mutex<HashMap<String, String>>,
+    }
+
+    impl CacheManager for SlowCache {
+        fn get(&self, key: &str) -> LocalBoxFuture<'_, Option<String>> {
+            let value = self.entries.lock().unwrap().get(key).cloned();
+            Box::pin(future::ready(value))
+        }
+
+        fn set(&self, key: &str, value: String, _: Duration) -> LocalBoxFuture<'_, ()> {
+            let key = key.to_string();
+            Box::pin(async move {
+                actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+                self.entries.lock().unwrap().insert(key, value);
+            })
+        }
+
+        fn increment(&self, _: &str, by: u64, _: Duration) -> LocalBoxFuture<'_, u64> {
+            Box::pin(future::ready(by))
+        }
+    }
+
+    /// A cache whose writes never complete, like a stalled Redis.
+    struct StalledCache;
+
+    impl CacheManager for StalledCache {
+        fn get(&self, _: &str) -> LocalBoxFuture<'_, Option<String>> {
+            Box::pin(future::ready(None))
+        }
+
+        fn set(&self, _: &str, _: String, _: Duration) -> LocalBoxFuture<'_, ()> {
+            Box::pin(future::pending())
+        }
+
+        fn increment(&self, _: &str, by: u64, _: Duration) -> LocalBoxFuture<'_, u64> {
+            Box::pin(future::ready(by))
+        }
+    }
+
+    async fn has_namespace(adapter: &Addr<LocalAdapter>) -> bool {
+        adapter.send(GetNamespace { app_id: "app".to_string() }).await.unwrap().is_some()
+    }
+
+    #[actix_web::test]
+    async fn namespace_lives_from_the_first_socket_to_the_last() {
+        let adapter = adapter(memory_cache());
+        assert!(!has_namespace(&adapter).await);
+
+        add_socket(&adapter, "1.1").await.unwrap();
+        assert!(has_namespace(&adapter).await);
+        add_socket(&adapter, "1.2").await.unwrap();
+
+        remove_socket(&adapter, "1.1");
+        assert!(has_namespace(&adapter).await);
+        remove_socket(&adapter, "1.2");
+        assert!(!has_namespace(&adapter).await);
+    }
+
+    #[actix_web::test]
+    async fn rejects_connections_over_the_app_limit() {
+        let app = AppConfig {
+            max_connections: Some(1),
+            ..app()
+        };
+        let adapter = adapter_with(app, None, memory_cache());
+        add_socket(&adapter, "1.1").await.unwrap();
+        assert!(matches!(add_socket(&adapter, "1.2").await, Err(PusherError::OverConnectionQuota)));
+
+        // The refused connection is not counted, so the namespace closes with the first.
+        remove_socket(&adapter, "1.1");
+        assert!(!has_namespace(&adapter).await);
+        add_socket(&adapter, "1.3").await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn rejects_connections_over_the_server_limit() {
+        let adapter = adapter_with(app(), Some(1), memory_cache());
+        add_socket(&adapter, "1.1").await.unwrap();
+        assert!(matches!(add_socket(&adapter, "1.2").await, Err(PusherError::OverCapacity)));
+
+        remove_socket(&adapter, "1.1");
+        add_socket(&adapter, "1.3").await.unwrap();
+    }
+
+    #[actix_web::test]
+    async fn rejects_connections_to_unknown_apps() {
+        let adapter = adapter(memory_cache());
+        let result = adapter
+            .send(AddSocket {
+                app_id: "missing".to_string(),
+                socket_id: "1.1".to_string(),
+                socket_addr: Socket::default().start().recipient(),
+            })
+            .await
+            .unwrap();
+        assert!(matches!(result, Err(PusherError::App(crate::app_manager::AppError::NotFound(_)))));
+        assert!(adapter.send(GetNamespace { app_id: "missing".to_string() }).await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn departing_sockets_leave_no_channels_behind() {
+        let (url, webhooks) = webhook_stub();
+        let app = AppConfig {
+            secret: Some("secret".to_string()),
+            has_member_removed_webhooks: true,
+            has_channel_vacated_webhooks: true,
+            webhooks: vec![WebhookConfig {
+                url,
+                event_types: vec![],
+                filter: Default::default(),
+            }],
+            ..app()
+        };
+        let adapter = adapter_with(app, None, memory_cache());
+        add_socket(&adapter, "1.0").await.unwrap();
+        subscribe(&adapter, "1.0", "other").await;
+        let socket_ids: Vec<String> = (1..=20).map(|i| format!("1.{}", i)).collect();
+        for (i, socket_id) in socket_ids.iter().enumerate() {
+            add_socket(&adapter, socket_id).await.unwrap();
+            subscribe(&adapter, socket_id, "news").await;
+            join_presence(&adapter, socket_id, &format!("user-{}", i % 5)).await;
+        }
+
+        for socket_id in &socket_ids {
+            remove_socket(&adapter, socket_id);
+        }
+        let namespace = adapter.send(GetNamespace { app_id: "app".to_string() }).await.unwrap().unwrap();
+        let occupied = namespace.send(GetOccupiedChannels { prefix: None }).await.unwrap();
+        assert_eq!(occupied.keys().collect::<Vec<_>>(), ["other"]);
+
+        // Webhooks go out in batches, so wait for the first one to be flushed.
+        actix_web::rt::time::sleep(Duration::from_millis(1500)).await;
+        let webhooks = webhooks.lock().unwrap();
+        let mut removed: Vec<&str> = webhooks
+            .iter()
+            .filter(|event| event["name"] == "member_removed")
+            .map(|event| event["user_id"].as_str().unwrap())
+            .collect();
+        removed.sort();
+        assert_eq!(removed, ["user-0", "user-1", "user-2", "user-3", "user-4"]);
+        let mut vacated: Vec<&str> = webhooks
+            .iter()
+            .filter(|event| event["name"] == "channel_vacated")
+            .map(|event| event["channel"].as_str().unwrap())
+            .collect();
+        vacated.sort();
+        assert_eq!(vacated, ["news", "presence-room"]);
+    }
+
+    #[actix_web::test]
+    async fn stalled_cache_writes_do_not_block_the_adapter() {
+        let adapter = adapter(Arc::new(StalledCache));
+        adapter.do_send(SendMessage {
+            app_id: "app".to_string(),
+            message: PusherApiMessage {
+                name: Some("update".to_string()),
+                data: Some("{}".to_string()),
+                channel: Some("cache-prices".to_string()),
+                channels: None,
+                socket_id: None,
+                info: None,
+            },
+        });
+        let other_work = async {
+            add_socket(&adapter, "1.1").await.unwrap();
+            subscribe(&adapter, "1.1", "news").await;
+            adapter
+                .send(GetCachedEvent {
+                    app_id: "app".to_string(),
+                    channel: "cache-other".to_string(),
+                })
+                .await
+                .unwrap()
+        };
+        let cached = tokio::time::timeout(Duration::from_secs(1), other_work).await;
+        assert_eq!(cached.ok(), Some(None));
+    }
+
+    #[actix_web::test]
+    async fn cached_event_is_readable_right_after_publishing() {
+        let adapter = adapter(Arc::new(SlowCache::default()));
+        adapter.do_send(SendMessage {
+            app_id: "app".to_string(),
+            message: PusherApiMessage {
+                name: Some("update".to_string()),
+                data: Some("{}".to_string()),
+                channel: Some("cache-prices".to_string()),
+                channels: None,
+                socket_id: None,
+                info: None,
+            },
+        });
+        let cached = adapter
+            .send(GetCachedEvent {
+                app_id: "app".to_string(),
+                channel: "cache-prices".to_string(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            cached,
+            Some(json!({"data": "{}", "channel": "cache-prices", "event": "update"}))
+        );
+    }
+
+    /// Starts `nodes` adapters linked through an in-memory cluster, followed
+    /// by one that never answers queries when `with_silent_node` is set.
+    fn cluster(nodes: usize, with_silent_node: bool) -> Vec<Addr<LocalAdapter>> {
+        let cluster = MemoryCluster::default();
+        let silent = (0..nodes).map(|_| false).chain(with_silent_node.then_some(true));
+        silent
+            .map(|silent| {
+                let adapter = adapter(memory_cache());
+                adapter.do_send(SetHorizontalAdapter(Box::new(cluster.join(adapter.clone(), silent))));
+                adapter
+            })
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn presence_rosters_are_merged_across_nodes() {
+        let nodes = cluster(2, false);
+        let first = add_socket(&nodes[0], "1.1").await.unwrap();
+        join_presence(&nodes[0], "1.1", "alice").await;
+        add_socket(&nodes[1], "2.1").await.unwrap();
+
+        let roster = join_presence(&nodes[1], "2.1", "bob").await;
+        assert_eq!(roster, HashMap::from([
+            ("alice".to_string(), json!({"name": "alice"})),
+            ("bob".to_string(), json!({"name": "bob"})),
+        ]));
+        let mut users = nodes[0]
+            .send(GetChannelUsers {
+                app_id: "app".to_string(),
+                channel: "presence-room".to_string(),
+            })
+            .await
+            .unwrap();
+        users.sort();
+        assert_eq!(users, ["alice", "bob"]);
+
+        settle().await;
+        let added = events(&first, "pusher_internal:member_added");
+        assert_eq!(added.len(), 1);
+        assert_eq!(added[0]["data"]["user_id"], "bob");
+    }
+
+    #[actix_web::test]
+    async fn member_removed_fires_when_the_last_node_loses_the_user() {
+        let nodes = cluster(2, false);
+        let observer = add_socket(&nodes[1], "2.9").await.unwrap();
+        join_presence(&nodes[1], "2.9", "bob").await;
+        add_socket(&nodes[0], "1.1").await.unwrap();
+        join_presence(&nodes[0], "1.1", "alice").await;
+        add_socket(&nodes[1], "2.1").await.unwrap();
+        join_presence(&nodes[1], "2.1", "alice").await;
+        settle().await;
+        assert_eq!(events(&observer, "pusher_internal:member_added").len(), 1);
+
+        remove_socket(&nodes[0], "1.1");
+        settle().await;
+        assert!(events(&observer, "pusher_internal:member_removed").is_empty());
+
+        remove_socket(&nodes[1], "2.1");
+        settle().await;
+        let removed = events(&observer, "pusher_internal:member_removed");
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0]["data"]["user_id"], "alice");
+    }
+
+    #[actix_web::test]
+    async fn a_socket_joins_a_presence_channel_once() {
+        let adapter = adapter(memory_cache());
+        let observer = add_socket(&adapter, "1.9").await.unwrap();
+        join_presence(&adapter, "1.9", "bob").await;
+        add_socket(&adapter, "1.1").await.unwrap();
+        join_presence(&adapter, "1.1", "alice").await;
+
+        let roster = join_presence(&adapter, "1.1", "mallory").await;
+        assert!(!roster.contains_key("mallory"));
+        remove_socket(&adapter, "1.1");
+        settle().await;
+
+        let added = events(&observer, "pusher_internal:member_added");
+        assert_eq!(added.len(), 1);
+        assert_eq!(added[0]["data"]["user_id"], "alice");
+        let removed = events(&observer, "pusher_internal:member_removed");
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0]["data"]["user_id"], "alice");
+        let users = adapter
+            .send(GetChannelUsers {
+                app_id: "app".to_string(),
+                channel: "presence-room".to_string(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(users, ["bob"]);
+    }
+
+    #[actix_web::test]
+    async fn subscription_counts_are_summed_across_nodes() {
+        let nodes = cluster(3, false);
+        for (node, socket_id) in [(0, "1.1"), (0, "1.2"), (1, "2.1"), (2, "3.1")] {
+            add_socket(&nodes[node], socket_id).await.unwrap();
+            subscribe(&nodes[node], socket_id, "news").await;
+        }
+        settle().await;
+
+        let started = std::time::Instant::now();
+        for node in &nodes {
+            assert_eq!(subscription_count(node, "news").await, Some(4));
+        }
+        // Every node answered, so nobody waited for the timeout.
+        assert!(started.elapsed() < REQUEST_TIMEOUT);
+    }
+
+    #[actix_web::test]
+    async fn silent_nodes_are_cut_off_after_the_request_timeout() {
+        let nodes = cluster(2, true);
+        for (node, socket_id) in [(0, "1.1"), (1, "2.1"), (2, "3.1")] {
+            add_socket(&nodes[node], socket_id).await.unwrap();
+            subscribe(&nodes[node], socket_id, "news").await;
+        }
+        settle().await;
+
+        let started = std::time::Instant::now();
+        assert_eq!(subscription_count(&nodes[0], "news").await, Some(2));
+        let elapsed = started.elapsed();
+        assert!(elapsed >= REQUEST_TIMEOUT);
+        assert!(elapsed < REQUEST_TIMEOUT + Duration::from_secs(1));
+    }
+}
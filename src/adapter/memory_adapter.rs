@@ -0,0 +1,81 @@
+use std::sync::{Arc, Mutex};
+use actix::Addr;
+use futures_util::future::LocalBoxFuture;
+use crate::adapter::local_adapter::{LocalAdapter, LocalQuery, RemoteMessage};
+use crate::adapter::{ClusterMessage, ClusterQuery, ClusterReply, ClusterResponse, HorizontalAdapter, PendingRequests, REQUEST_TIMEOUT};
+use crate::utils;
+
+struct Node {
+    node_id: String,
+    local_adapter: Addr<LocalAdapter>,
+    /// Receives broadcasts but never answers queries, like a hung node.
+    silent: bool,
+}
+
+/// Links `LocalAdapter`s living in the same process, so tests can run a
+/// cluster without a broker.
+#[derive(Clone, Default)]
+pub struct MemoryCluster {
+    nodes: Arc<Mutex<Vec<Node>>>,
+}
+
+impl MemoryCluster {
+    /// Adds a node, returning the horizontal adapter to hand its `LocalAdapter`.
+    pub fn join(&self, local_adapter: Addr<LocalAdapter>, silent: bool) -> MemoryAdapter {
+        let adapter = MemoryAdapter {
+            node_id: utils::generate_node_id(),
+            cluster: self.clone(),
+            pending: PendingRequests::default(),
+        };
+        self.nodes.lock().unwrap().push(Node {
+            node_id: adapter.node_id.clone(),
+            local_adapter,
+            silent,
+        });
+        adapter
+    }
+}
+
+pub struct MemoryAdapter {
+    node_id: String,
+    cluster: MemoryCluster,
+    pending: PendingRequests,
+}
+
+impl HorizontalAdapter for MemoryAdapter {
+    fn publish(&self, message: ClusterMessage) {
+        for node in self.cluster.nodes.lock().unwrap().iter() {
+            if node.node_id != self.node_id {
+                node.local_adapter.do_send(RemoteMessage(message.clone()));
+            }
+        }
+    }
+
+    fn request(&self, app_id: &str, query: ClusterQuery) -> LocalBoxFuture<'static, Vec<ClusterReply>> {
+        let request_id = utils::generate_node_id();
+        let receiver = self.pending.register(&request_id);
+        let mut other_nodes = 0;
+        for node in self.cluster.nodes.lock().unwrap().iter() {
+            if node.node_id == self.node_id {
+                continue;
+            }
+            other_nodes += 1;
+            if node.silent {
+                continue;
+            }
+            let answer = node.local_adapter.send(LocalQuery {
+                app_id: app_id.to_string(),
+                query: query.clone(),
+            });
+            let pending = self.pending.clone();
+            let request_id = request_id.clone();
+            actix::spawn(async move {
+                if let Ok(reply) = answer.await {
+                    pending.resolve(ClusterResponse { request_id, reply });
+                }
+            });
+        }
+        let pending = self.pending.clone();
+        Box::pin(async move { pending.wait(&request_id, other_nodes, receiver, REQUEST_TIMEOUT).await })
+    }
+}
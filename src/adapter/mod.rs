@@ -1 +1,145 @@
-pub mod local_adapter;
\ No newline at end of file
+pub mod local_adapter;
+#[cfg(test)]
+pub mod memory_adapter;
+#[cfg(test)]
+pub mod testing;
+pub mod nats_adapter;
+pub mod redis_adapter;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use futures_util::future::LocalBoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::oneshot;
+use crate::message::PusherApiMessage;
+use crate::namespace::ChannelMembers;
+
+/// How long a node waits for the others to answer a cluster query.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The cross-node half of the adapter. The `LocalAdapter` owns the sockets of
+/// this node and uses a horizontal adapter, when one is configured, to relay
+/// broadcasts to the other nodes and to collect their share of cluster-wide state.
+pub trait HorizontalAdapter: Send {
+    /// Relays a broadcast to every other node.
+    fn publish(&self, message: ClusterMessage);
+
+    /// Asks every other node about an app, returning the replies that arrive
+    /// within `REQUEST_TIMEOUT`.
+    fn request(&self, app_id: &str, query: ClusterQuery) -> LocalBoxFuture<'static, Vec<ClusterReply>>;
+}
+
+/// A broadcast the receiving nodes deliver to their own sockets.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClusterMessage {
+    Broadcast {
+        app_id: String,
+        message: PusherApiMessage,
+    },
+    SendToChannel {
+        app_id: String,
+        channel: String,
+        message: Value,
+        except: Option<String>,
+    },
+}
+
+/// A question about the local state of an app on another node.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClusterQuery {
+    ChannelMembers { channel: String },
+    PresenceMembers { channel: String },
+    OccupiedChannels { prefix: Option<String> },
+    SocketCount,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum ClusterReply {
+    ChannelMembers(ChannelMembers),
+    /// A presence roster as `user_id => user_info`.
+    PresenceMembers(HashMap<String, Value>),
+    OccupiedChannels(HashMap<String, ChannelMembers>),
+    SocketCount(u64),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ClusterBroadcast {
+    pub node_id: String,
+    pub message: ClusterMessage,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ClusterRequest {
+    pub node_id: String,
+    pub request_id: String,
+    pub app_id: String,
+    pub query: ClusterQuery,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ClusterResponse {
+    pub request_id: String,
+    pub reply: ClusterReply,
+}
+
+struct PendingRequest {
+    replies: Vec<ClusterReply>,
+    /// Unknown until the request was published.
+    expected: Option<usize>,
+    done: Option<oneshot::Sender<()>>,
+}
+
+impl PendingRequest {
+    fn complete_if_answered(&mut self) {
+        if self.expected.is_some_and(|expected| self.replies.len() >= expected) {
+            if let Some(done) = self.done.take() {
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+/// Collects the responses to this node's cluster queries.
+#[derive(Clone, Default)]
+pub struct PendingRequests {
+    requests: Arc<Mutex<HashMap<String, PendingRequest>>>,
+}
+
+impl PendingRequests {
+    /// Starts collecting responses; call before publishing the request so none are missed.
+    pub fn register(&self, request_id: &str) -> oneshot::Receiver<()> {
+        let (done, receiver) = oneshot::channel();
+        self.requests.lock().unwrap().insert(request_id.to_string(), PendingRequest {
+            replies: vec![],
+            expected: None,
+            done: Some(done),
+        });
+        receiver
+    }
+
+    pub fn resolve(&self, response: ClusterResponse) {
+        if let Some(request) = self.requests.lock().unwrap().get_mut(&response.request_id) {
+            request.replies.push(response.reply);
+            request.complete_if_answered();
+        }
+    }
+
+    /// Waits until `expected` replies arrived, or `timeout` elapsed when some node never answers.
+    pub async fn wait(&self, request_id: &str, expected: usize, receiver: oneshot::Receiver<()>, timeout: Duration) -> Vec<ClusterReply> {
+        if let Some(request) = self.requests.lock().unwrap().get_mut(request_id) {
+            request.expected = Some(expected);
+            request.complete_if_answered();
+        }
+        let _ = tokio::time::timeout(timeout, receiver).await;
+        self.requests
+            .lock()
+            .unwrap()
+            .remove(request_id)
+            .map_or_else(Vec::new, |request| request.replies)
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use actix::Addr;
+use async_nats::{Client, Message};
+use futures_util::future::LocalBoxFuture;
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use crate::adapter::local_adapter::{LocalAdapter, LocalQuery, RemoteMessage};
+use crate::adapter::{
+    ClusterBroadcast, ClusterMessage, ClusterQuery, ClusterReply, ClusterRequest, ClusterResponse,
+    HorizontalAdapter, PendingRequests, REQUEST_TIMEOUT,
+};
+use crate::config::NatsAdapterConfig;
+use crate::log::Log;
+use crate::utils;
+
+/// A node that missed this many heartbeats is no longer waited for.
+const MISSED_HEARTBEATS: u32 = 3;
+
+#[derive(Serialize, Deserialize)]
+struct NodeHeartbeat {
+    node_id: String,
+}
+
+/// Relays broadcasts and cluster queries between nodes over NATS.
+///
+/// Broadcasts go to `{prefix}.broadcast` and queries to `{prefix}.requests`,
+/// answered on the reply subject `{prefix}.responses.{node_id}`. NATS does not
+/// tell a publisher how many subscribers it reached, so nodes announce
+/// themselves on `{prefix}.nodes` and a query waits for every live node.
+/// Messages carry the sender's node id so a node ignores its own.
+pub struct NatsAdapter {
+    node_id: String,
+    prefix: String,
+    client: Client,
+    pending: PendingRequests,
+    nodes: Arc<Mutex<HashMap<String, Instant>>>,
+    heartbeat_interval: Duration,
+}
+
+impl NatsAdapter {
+    /// Connects to NATS and starts relaying cluster traffic to `local_adapter`.
+    pub async fn connect(config: &NatsAdapterConfig, local_adapter: Addr<LocalAdapter>) -> Result<Self, async_nats::Error> {
+        let client = async_nats::connect(config.url.as_str()).await?;
+        let adapter = NatsAdapter {
+            node_id: utils::generate_node_id(),
+            prefix: config.prefix.clone(),
+            client,
+            pending: PendingRequests::default(),
+            nodes: Arc::new(Mutex::new(HashMap::new())),
+            heartbeat_interval: Duration::from_secs(config.heartbeat_interval.max(1)),
+        };
+        let subscriptions = vec![
+            adapter.client.subscribe(broadcast_subject(&adapter.prefix)).await?,
+            adapter.client.subscribe(request_subject(&adapter.prefix)).await?,
+            adapter.client.subscribe(response_subject(&adapter.prefix, &adapter.node_id)).await?,
+            adapter.client.subscribe(nodes_subject(&adapter.prefix)).await?,
+        ];
+        let listener = adapter.listener();
+        actix::spawn(heartbeat(
+            listener.client.clone(),
+            listener.prefix.clone(),
+            listener.node_id.clone(),
+            adapter.heartbeat_interval,
+        ));
+        actix::spawn(listen(futures_util::stream::select_all(subscriptions), listener, local_adapter));
+        Log::cluster_title(format!("Node {} joined the cluster through NATS at {}", adapter.node_id, config.url).as_str());
+        Ok(adapter)
+    }
+
+    fn listener(&self) -> Listener {
+        Listener {
+            node_id: self.node_id.clone(),
+            prefix: self.prefix.clone(),
+            client: self.client.clone(),
+            pending: self.pending.clone(),
+            nodes: self.nodes.clone(),
+        }
+    }
+
+    /// Nodes heard from recently, this one excluded.
+    fn live_nodes(&self) -> usize {
+        let mut nodes = self.nodes.lock().unwrap();
+        nodes.retain(|_, last_seen| last_seen.elapsed() < self.heartbeat_interval * MISSED_HEARTBEATS);
+        nodes.len()
+    }
+}
+
+fn broadcast_subject(prefix: &str) -> String {
+    format!("{}.broadcast", prefix)
+}
+
+fn request_subject(prefix: &str) -> String {
+    format!("{}.requests", prefix)
+}
+
+fn response_subject(prefix: &str, node_id: &str) -> String {
+    format!("{}.responses.{}", prefix, node_id)
+}
+
+fn nodes_subject(prefix: &str) -> String {
+    format!("{}.nodes", prefix)
+}
+
+async fn publish(client: Client, subject: String, reply: Option<String>, payload: String) {
+    let result = match reply {
+        Some(reply) => client.publish_with_reply(subject.clone(), reply, payload.into()).await,
+        None => client.publish(subject.clone(), payload.into()).await,
+    };
+    if let Err(e) = result {
+        Log::error_title(format!("Could not publish to NATS subject {}: {}", subject, e).as_str());
+    }
+}
+
+async fn announce(client: Client, prefix: String, node_id: String) {
+    let payload = serde_json::to_string(&NodeHeartbeat { node_id }).expect("heartbeats serialize to JSON");
+    publish(client, nodes_subject(&prefix), None, payload).await;
+}
+
+async fn heartbeat(client: Client, prefix: String, node_id: String, every: Duration) {
+    let mut interval = tokio::time::interval(every);
+    loop {
+        interval.tick().await;
+        announce(client.clone(), prefix.clone(), node_id.clone()).await;
+    }
+}
+
+impl HorizontalAdapter for NatsAdapter {
+    fn publish(&self, message: ClusterMessage) {
+        let payload = serde_json::to_string(&ClusterBroadcast {
+            node_id: self.node_id.clone(),
+            message,
+        }).expect("cluster messages serialize to JSON");
+        actix::spawn(publish(self.client.clone(), broadcast_subject(&self.prefix), None, payload));
+    }
+
+    fn request(&self, app_id: &str, query: ClusterQuery) -> LocalBoxFuture<'static, Vec<ClusterReply>> {
+        let request_id = utils::generate_node_id();
+        let payload = serde_json::to_string(&ClusterRequest {
+            node_id: self.node_id.clone(),
+            request_id: request_id.clone(),
+            app_id: app_id.to_string(),
+            query,
+        }).expect("cluster requests serialize to JSON");
+        let receiver = self.pending.register(&request_id);
+        let pending = self.pending.clone();
+        let other_nodes = self.live_nodes();
+        let sent = publish(
+            self.client.clone(),
+            request_subject(&self.prefix),
+            Some(response_subject(&self.prefix, &self.node_id)),
+            payload,
+        );
+        Box::pin(async move {
+            sent.await;
+            pending.wait(&request_id, other_nodes, receiver, REQUEST_TIMEOUT).await
+        })
+    }
+}
+
+/// The state the subscription task needs to route incoming traffic.
+struct Listener {
+    node_id: String,
+    prefix: String,
+    client: Client,
+    pending: PendingRequests,
+    nodes: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+impl Listener {
+    fn handle(&self, message: Message, local_adapter: &Addr<LocalAdapter>) -> serde_json::Result<()> {
+        let subject = message.subject.as_str();
+        if subject == broadcast_subject(&self.prefix) {
+            let broadcast: ClusterBroadcast = serde_json::from_slice(&message.payload)?;
+            if broadcast.node_id != self.node_id {
+                local_adapter.do_send(RemoteMessage(broadcast.message));
+            }
+        } else if subject == request_subject(&self.prefix) {
+            let request: ClusterRequest = serde_json::from_slice(&message.payload)?;
+            if request.node_id != self.node_id {
+                if let Some(reply) = message.reply {
+                    actix::spawn(answer(request, reply.to_string(), self.client.clone(), local_adapter.clone()));
+                }
+            }
+        } else if subject == nodes_subject(&self.prefix) {
+            let heartbeat: NodeHeartbeat = serde_json::from_slice(&message.payload)?;
+            if heartbeat.node_id != self.node_id {
+                let is_new = self.nodes.lock().unwrap().insert(heartbeat.node_id, Instant::now()).is_none();
+                // Answer a newcomer right away instead of at the next heartbeat.
+                if is_new {
+                    actix::spawn(announce(self.client.clone(), self.prefix.clone(), self.node_id.clone()));
+                }
+            }
+        } else {
+            self.pending.resolve(serde_json::from_slice(&message.payload)?);
+        }
+        Ok(())
+    }
+}
+
+async fn answer(request: ClusterRequest, reply: String, client: Client, local_adapter: Addr<LocalAdapter>) {
+    let Ok(cluster_reply) = local_adapter.send(LocalQuery {
+        app_id: request.app_id,
+        query: request.query,
+    }).await else {
+        return;
+    };
+    let payload = serde_json::to_string(&ClusterResponse {
+        request_id: request.request_id,
+        reply: cluster_reply,
+    }).expect("cluster responses serialize to JSON");
+    publish(client, reply, None, payload).await;
+}
+
+async fn listen(mut messages: futures_util::stream::SelectAll<async_nats::Subscriber>, listener: Listener, local_adapter: Addr<LocalAdapter>) {
+    while let Some(message) = messages.next().await {
+        if let Err(e) = listener.handle(message, &local_adapter) {
+            Log::warning_title(format!("Ignoring malformed cluster message: {}", e).as_str());
+        }
+    }
+    Log::error_title("Lost the NATS subscriptions; this node no longer receives cluster traffic");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapter::testing::*;
+
+    // Run against a nats-server with `NATS_URL=nats://127.0.0.1:4222 cargo test -- --ignored`.
+
+    fn config() -> NatsAdapterConfig {
+        NatsAdapterConfig {
+            url: std::env::var("NATS_URL").expect("NATS_URL points at a nats-server"),
+            // A cluster of its own, so concurrent tests on the same server stay apart.
+            prefix: format!("test-{}", utils::generate_node_id()),
+            heartbeat_interval: 1,
+        }
+    }
+
+    async fn sleep(duration: Duration) {
+        actix_web::rt::time::sleep(duration).await;
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a nats-server at NATS_URL"]
+    async fn nodes_that_miss_heartbeats_are_dropped() {
+        let config = config();
+        let node = NatsAdapter::connect(&config, adapter(memory_cache())).await.unwrap();
+        let _peer = NatsAdapter::connect(&config, adapter(memory_cache())).await.unwrap();
+        // A node that announces itself once, then hangs without answering queries.
+        let ghost = async_nats::connect(config.url.as_str()).await.unwrap();
+        let heartbeat = serde_json::to_string(&NodeHeartbeat { node_id: "ghost".to_string() }).unwrap();
+        ghost.publish(nodes_subject(&config.prefix), heartbeat.into()).await.unwrap();
+        ghost.flush().await.unwrap();
+        sleep(Duration::from_millis(300)).await;
+        assert_eq!(node.live_nodes(), 2);
+
+        // While the ghost counts as live, queries wait for it until the timeout.
+        let started = Instant::now();
+        let replies = node.request("app", ClusterQuery::SocketCount).await;
+        assert_eq!(replies.len(), 1);
+        assert!(started.elapsed() >= REQUEST_TIMEOUT);
+
+        sleep(node.heartbeat_interval * MISSED_HEARTBEATS).await;
+        assert_eq!(node.live_nodes(), 1, "the peer kept beating, the ghost did not");
+
+        let started = Instant::now();
+        let replies = node.request("app", ClusterQuery::SocketCount).await;
+        assert_eq!(replies.len(), 1);
+        assert!(started.elapsed() < REQUEST_TIMEOUT);
+    }
+}
@@ -0,0 +1,245 @@
+use actix::Addr;
+use futures_util::future::LocalBoxFuture;
+use futures_util::StreamExt;
+use redis::aio::{MultiplexedConnection, PubSub};
+use redis::RedisResult;
+use crate::adapter::local_adapter::{LocalAdapter, LocalQuery, RemoteMessage};
+use crate::adapter::{
+    ClusterBroadcast, ClusterMessage, ClusterQuery, ClusterReply, ClusterRequest, ClusterResponse,
+    HorizontalAdapter, PendingRequests, REQUEST_TIMEOUT,
+};
+use crate::config::RedisAdapterConfig;
+use crate::log::Log;
+use crate::utils;
+
+/// Relays broadcasts and cluster queries between nodes over Redis pub/sub.
+///
+/// Every node subscribes to `{prefix}#broadcast` and `{prefix}#requests`, and
+/// receives the answers to its own queries on `{prefix}#responses#{node_id}`.
+pub struct RedisAdapter {
+    node_id: String,
+    prefix: String,
+    connection: MultiplexedConnection,
+    pending: PendingRequests,
+}
+
+impl RedisAdapter {
+    /// Connects to Redis and starts relaying cluster traffic to `local_adapter`.
+    pub async fn connect(config: &RedisAdapterConfig, local_adapter: Addr<LocalAdapter>) -> RedisResult<Self> {
+        let client = redis::Client::open(config.url.as_str())?;
+        let connection = client.get_multiplexed_async_connection().await?;
+        let adapter = RedisAdapter {
+            node_id: utils::generate_node_id(),
+            prefix: config.prefix.clone(),
+            connection,
+            pending: PendingRequests::default(),
+        };
+        let mut pubsub = client.get_async_pubsub().await?;
+        pubsub.subscribe(broadcast_channel(&adapter.prefix)).await?;
+        pubsub.subscribe(request_channel(&adapter.prefix)).await?;
+        pubsub.subscribe(response_channel(&adapter.prefix, &adapter.node_id)).await?;
+        actix::spawn(listen(pubsub, adapter.listener(), local_adapter));
+        Log::cluster_title(format!("Node {} joined the cluster through Redis at {}", adapter.node_id, config.url).as_str());
+        Ok(adapter)
+    }
+
+    fn listener(&self) -> Listener {
+        Listener {
+            node_id: self.node_id.clone(),
+            prefix: self.prefix.clone(),
+            connection: self.connection.clone(),
+            pending: self.pending.clone(),
+        }
+    }
+}
+
+fn broadcast_channel(prefix: &str) -> String {
+    format!("{}#broadcast", prefix)
+}
+
+fn request_channel(prefix: &str) -> String {
+    format!("{}#requests", prefix)
+}
+
+fn response_channel(prefix: &str, node_id: &str) -> String {
+    format!("{}#responses#{}", prefix, node_id)
+}
+
+/// Publishes `payload` and returns how many subscribers received it.
+async fn publish(mut connection: MultiplexedConnection, channel: String, payload: String) -> usize {
+    let result = redis::cmd("PUBLISH")
+        .arg(&channel)
+        .arg(payload)
+        .query_async(&mut connection)
+        .await;
+    result.unwrap_or_else(|e| {
+        Log::error_title(format!("Could not publish to Redis channel {}: {}", channel, e).as_str());
+        0
+    })
+}
+
+impl HorizontalAdapter for RedisAdapter {
+    fn publish(&self, message: ClusterMessage) {
+        let payload = serde_json::to_string(&ClusterBroadcast {
+            node_id: self.node_id.clone(),
+            message,
+        }).expect("cluster messages serialize to JSON");
+        actix::spawn(publish(self.connection.clone(), broadcast_channel(&self.prefix), payload));
+    }
+
+    fn request(&self, app_id: &str, query: ClusterQuery) -> LocalBoxFuture<'static, Vec<ClusterReply>> {
+        let request_id = utils::generate_node_id();
+        let payload = serde_json::to_string(&ClusterRequest {
+            node_id: self.node_id.clone(),
+            request_id: request_id.clone(),
+            app_id: app_id.to_string(),
+            query,
+        }).expect("cluster requests serialize to JSON");
+        let receiver = self.pending.register(&request_id);
+        let pending = self.pending.clone();
+        let sent = publish(self.connection.clone(), request_channel(&self.prefix), payload);
+        Box::pin(async move {
+            // Every node receives the request, this one included.
+            let other_nodes = sent.await.saturating_sub(1);
+            pending.wait(&request_id, other_nodes, receiver, REQUEST_TIMEOUT).await
+        })
+    }
+}
+
+/// The state the subscription task needs to route incoming traffic.
+struct Listener {
+    node_id: String,
+    prefix: String,
+    connection: MultiplexedConnection,
+    pending: PendingRequests,
+}
+
+impl Listener {
+    fn handle(&self, channel: &str, payload: &str, local_adapter: &Addr<LocalAdapter>) -> serde_json::Result<()> {
+        if channel == broadcast_channel(&self.prefix) {
+            let broadcast: ClusterBroadcast = serde_json::from_str(payload)?;
+            if broadcast.node_id != self.node_id {
+                local_adapter.do_send(RemoteMessage(broadcast.message));
+            }
+        } else if channel == request_channel(&self.prefix) {
+            let request: ClusterRequest = serde_json::from_str(payload)?;
+            if request.node_id != self.node_id {
+                let channel = response_channel(&self.prefix, &request.node_id);
+                actix::spawn(answer(request, channel, self.connection.clone(), local_adapter.clone()));
+            }
+        } else {
+            self.pending.resolve(serde_json::from_str(payload)?);
+        }
+        Ok(())
+    }
+}
+
+async fn answer(request: ClusterRequest, response_channel: String, connection: MultiplexedConnection, local_adapter: Addr<LocalAdapter>) {
+    let Ok(reply) = local_adapter.send(LocalQuery {
+        app_id: request.app_id,
+        query: request.query,
+    }).await else {
+        return;
+    };
+    let payload = serde_json::to_string(&ClusterResponse {
+        request_id: request.request_id,
+        reply,
+    }).expect("cluster responses serialize to JSON");
+    publish(connection, response_channel, payload).await;
+}
+
+async fn listen(pubsub: PubSub, listener: Listener, local_adapter: Addr<LocalAdapter>) {
+    let mut messages = pubsub.into_on_message();
+    while let Some(message) = messages.next().await {
+        let Ok(payload) = message.get_payload::<String>() else {
+            continue;
+        };
+        if let Err(e) = listener.handle(message.get_channel_name(), &payload, &local_adapter) {
+            Log::warning_title(format!("Ignoring malformed cluster message: {}", e).as_str());
+        }
+    }
+    Log::error_title("Lost the Redis subscription; this node no longer receives cluster traffic");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+    use crate::adapter::local_adapter::{SendMessage, SetHorizontalAdapter};
+    use crate::adapter::testing::*;
+    use crate::message::PusherApiMessage;
+
+    // Run against a redis-server with `REDIS_URL=redis://127.0.0.1:6379 cargo test -- --ignored`.
+
+    /// Starts a node joined to the cluster under `prefix`.
+    async fn node(prefix: &str) -> Addr<LocalAdapter> {
+        let config = RedisAdapterConfig {
+            url: std::env::var("REDIS_URL").expect("REDIS_URL points at a redis-server"),
+            prefix: prefix.to_string(),
+        };
+        let local_adapter = adapter(memory_cache());
+        let redis = RedisAdapter::connect(&config, local_adapter.clone()).await.unwrap();
+        local_adapter.send(SetHorizontalAdapter(Box::new(redis))).await.unwrap();
+        local_adapter
+    }
+
+    /// A cluster of its own, so concurrent tests on the same server stay apart.
+    fn prefix() -> String {
+        format!("test-{}", utils::generate_node_id())
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a redis-server at REDIS_URL"]
+    async fn broadcasts_reach_the_sockets_of_every_node() {
+        let prefix = prefix();
+        let nodes = [node(&prefix).await, node(&prefix).await, node(&prefix).await];
+        let mut sockets = vec![];
+        for (i, node) in nodes.iter().enumerate() {
+            let socket_id = format!("{}.1", i);
+            sockets.push(add_socket(node, &socket_id).await.unwrap());
+            subscribe(node, &socket_id, "news").await;
+        }
+
+        nodes[0].do_send(SendMessage {
+            app_id: "app".to_string(),
+            message: PusherApiMessage {
+                name: Some("update".to_string()),
+                data: Some("{}".to_string()),
+                channel: Some("news".to_string()),
+                channels: None,
+                socket_id: None,
+                info: None,
+            },
+        });
+        actix_web::rt::time::sleep(Duration::from_millis(500)).await;
+
+        for frames in &sockets {
+            let updates = events(frames, "update");
+            assert_eq!(updates.len(), 1, "each socket gets the event exactly once");
+            assert_eq!(updates[0]["channel"], "news");
+        }
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a redis-server at REDIS_URL"]
+    async fn queries_are_answered_by_every_node() {
+        let prefix = prefix();
+        let nodes = [node(&prefix).await, node(&prefix).await];
+        add_socket(&nodes[0], "1.1").await.unwrap();
+        subscribe(&nodes[0], "1.1", "news").await;
+        join_presence(&nodes[0], "1.1", "alice").await;
+        add_socket(&nodes[1], "2.1").await.unwrap();
+        subscribe(&nodes[1], "2.1", "news").await;
+
+        let roster = join_presence(&nodes[1], "2.1", "bob").await;
+        let mut users: Vec<&String> = roster.keys().collect();
+        users.sort();
+        assert_eq!(users, ["alice", "bob"]);
+
+        let started = Instant::now();
+        assert_eq!(subscription_count(&nodes[0], "news").await, Some(2));
+        assert_eq!(subscription_count(&nodes[1], "news").await, Some(2));
+        // Both replies arrived, so neither request waited for the timeout.
+        assert!(started.elapsed() < REQUEST_TIMEOUT);
+    }
+}
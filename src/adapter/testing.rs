@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use actix::{Actor, Addr, Context, Handler};
+use actix_web::{web, App, HttpResponse, HttpServer};
+use serde_json::{json, Value};
+use crate::adapter::local_adapter::{AddSocket, AddToChannel, GetChannelInfo, JoinPresenceChannel, LocalAdapter, RemoveSocket};
+use crate::app::AppConfig;
+use crate::app_manager::AppManager;
+use crate::cache::memory_cache_manager::MemoryCacheManager;
+use crate::cache::CacheManager;
+use crate::channel_managers::presence_channel_manager::PresenceMember;
+use crate::message::PusherApiMessageInfo;
+use crate::pusher_error::PusherError;
+use crate::webhook::webhook_sender::WebhookSender;
+use crate::ws_message::OnPusherMessage;
+
+// Helpers shared by the adapter tests, which run nodes with sockets of the app `app`.
+
+pub type Frames = Arc<Mutex<Vec<Value>>>;
+
+/// Stands in for a websocket connection, keeping the frames it is sent.
+#[derive(Default)]
+pub struct Socket {
+    pub frames: Frames,
+}
+
+impl Actor for Socket {
+    type Context = Context<Self>;
+}
+
+impl Handler<OnPusherMessage> for Socket {
+    type Result = ();
+
+    fn handle(&mut self, msg: OnPusherMessage, _: &mut Self::Context) {
+        self.frames.lock().unwrap().push(serde_json::from_str(&msg.message).unwrap());
+    }
+}
+
+pub fn app() -> AppConfig {
+    AppConfig {
+        id: "app".to_string(),
+        key: "key".to_string(),
+        enabled: true,
+        ..Default::default()
+    }
+}
+
+pub fn adapter_with(app: AppConfig, max_connections: Option<u64>, cache: Arc<dyn CacheManager>) -> Addr<LocalAdapter> {
+    let app_manager = Arc::new(AppManager::new(vec![app]));
+    let webhook_sender = WebhookSender::new(app_manager.clone()).start();
+    LocalAdapter::new(app_manager, max_connections, webhook_sender, cache).start()
+}
+
+pub fn adapter(cache: Arc<dyn CacheManager>) -> Addr<LocalAdapter> {
+    adapter_with(app(), None, cache)
+}
+
+pub async fn add_socket(adapter: &Addr<LocalAdapter>, socket_id: &str) -> Result<Frames, PusherError> {
+    let socket = Socket::default();
+    let frames = socket.frames.clone();
+    adapter
+        .send(AddSocket {
+            app_id: "app".to_string(),
+            socket_id: socket_id.to_string(),
+            socket_addr: socket.start().recipient(),
+        })
+        .await
+        .unwrap()
+        .map(|_| frames)
+}
+
+pub fn remove_socket(adapter: &Addr<LocalAdapter>, socket_id: &str) {
+    adapter.do_send(RemoveSocket {
+        app_id: "app".to_string(),
+        socket_id: socket_id.to_string(),
+    });
+}
+
+pub fn memory_cache() -> Arc<dyn CacheManager> {
+    Arc::new(MemoryCacheManager::new(100))
+}
+
+/// Lets relayed messages reach the sockets.
+pub async fn settle() {
+    actix_web::rt::time::sleep(Duration::from_millis(50)).await;
+}
+
+pub async fn subscribe(adapter: &Addr<LocalAdapter>, socket_id: &str, channel: &str) {
+    adapter
+        .send(AddToChannel {
+            app_id: "app".to_string(),
+            socket_id: socket_id.to_string(),
+            channel: channel.to_string(),
+        })
+        .await
+        .unwrap();
+}
+
+pub async fn join_presence(adapter: &Addr<LocalAdapter>, socket_id: &str, user_id: &str) -> HashMap<String, Value> {
+    adapter
+        .send(JoinPresenceChannel {
+            app_id: "app".to_string(),
+            channel: "presence-room".to_string(),
+            socket_id: socket_id.to_string(),
+            member: PresenceMember {
+                user_id: user_id.to_string(),
+                user_info: json!({"name": user_id}),
+            },
+        })
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+pub async fn subscription_count(adapter: &Addr<LocalAdapter>, channel: &str) -> Option<usize> {
+    let info = adapter
+        .send(GetChannelInfo {
+            app_id: "app".to_string(),
+            channel: channel.to_string(),
+            info: PusherApiMessageInfo {
+                user_count: false,
+                subscription_count: true,
+            },
+        })
+        .await
+        .unwrap();
+    info.subscription_count
+}
+
+/// Starts a webhook endpoint, returning its url and the events it receives.
+pub fn webhook_stub() -> (String, Frames) {
+    let received = Frames::default();
+    let events = received.clone();
+    let server = HttpServer::new(move || {
+        let events = events.clone();
+        App::new().route("/webhook", web::post().to(move |body: web::Json<Value>| {
+            let events = events.clone();
+            async move {
+                let batch = body["events"].as_array().cloned().unwrap_or_default();
+                events.lock().unwrap().extend(batch);
+                HttpResponse::Ok().finish()
+            }
+        }))
+    })
+    .workers(1)
+    .bind(("127.0.0.1", 0))
+    .unwrap();
+    let url = format!("http://127.0.0.1:{}/webhook", server.addrs()[0].port());
+    actix_web::rt::spawn(server.run());
+    (url, received)
+}
+
+pub fn events<'a>(frames: &'a Frames, event: &'a str) -> Vec<Value> {
+    frames.lock().unwrap().iter().filter(|frame| frame["event"] == event).cloned().collect()
+}
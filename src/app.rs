@@ -1,11 +1,20 @@
+use std::time::Duration;
 use serde::{Deserialize, Serialize};
-use serde_json::{from_str, Value};
-use std::collections::HashMap;
+use crate::webhook::WebhookEvent;
 
 fn true_() -> bool {
     true
 }
-#[derive(Debug, Serialize, Deserialize, Default)]
+
+// Pusher's limits, used when an app does not override them.
+const DEFAULT_MAX_CHANNEL_NAME_LENGTH: u64 = 200;
+const DEFAULT_MAX_EVENT_NAME_LENGTH: u64 = 200;
+const DEFAULT_MAX_EVENT_PAYLOAD_IN_KB: u64 = 10;
+const DEFAULT_MAX_EVENT_CHANNEL_AT_ONCE: u64 = 100;
+const DEFAULT_MAX_EVENT_BATCH_SIZE: u64 = 10;
+const DEFAULT_CACHE_TTL_IN_SECONDS: u64 = 30 * 60;
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
 pub struct AppConfig {
     pub id: String, //These can't be null
     pub key: String,
@@ -19,13 +28,15 @@ pub struct AppConfig {
     pub max_client_events_per_second: Option<u64>,
     pub max_read_requests_per_minute: Option<u64>,
     #[serde(default)]
-    pub webhooks: Vec<Value>,
+    pub webhooks: Vec<WebhookConfig>,
     pub max_presence_member_size_in_kb: Option<u64>,
     pub max_channel_name_length: Option<u64>,
     pub max_event_channel_at_once: Option<u64>,
     pub max_event_name_length: Option<u64>,
     pub max_event_payload_in_kb: Option<u64>,
     pub max_event_batch_size: Option<u64>,
+    /// How long cache channels remember their last event.
+    pub cache_ttl_in_seconds: Option<u64>,
     #[serde(default = "true_")]
     pub enable_user_authentication: bool,
     #[serde(default)]
@@ -40,4 +51,124 @@ pub struct AppConfig {
     pub has_member_removed_webhooks: bool,
     #[serde(default)]
     pub has_cache_missed_webhooks: bool,
-}
\ No newline at end of file
+}
+
+/// A webhook endpoint and the events it wants; no `event_types` means all of them.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default)]
+    pub event_types: Vec<String>,
+    #[serde(default)]
+    pub filter: WebhookFilter,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct WebhookFilter {
+    pub channel_name_starts_with: Option<String>,
+    pub channel_name_ends_with: Option<String>,
+}
+
+impl WebhookConfig {
+    pub fn accepts(&self, event: &WebhookEvent) -> bool {
+        let channel = event.channel();
+        (self.event_types.is_empty() || self.event_types.iter().any(|name| name == event.name()))
+            && self.filter.channel_name_starts_with.as_deref().is_none_or(|prefix| channel.starts_with(prefix))
+            && self.filter.channel_name_ends_with.as_deref().is_none_or(|suffix| channel.ends_with(suffix))
+    }
+}
+
+impl AppConfig {
+    /// Whether events named `event` are reported: the app's `has_*_webhooks`
+    /// flag must be set and a webhook must list the event or list nothing.
+    pub fn sends_webhook(&self, event: &str) -> bool {
+        let flag = match event {
+            "channel_occupied" => self.has_channel_occupied_webhooks,
+            "channel_vacated" => self.has_channel_vacated_webhooks,
+            "member_added" => self.has_member_added_webhooks,
+            "member_removed" => self.has_member_removed_webhooks,
+            "client_event" => self.has_client_event_webhooks,
+            "cache_miss" => self.has_cache_missed_webhooks,
+            _ => false,
+        };
+        flag && self.webhooks.iter().any(|webhook| {
+            webhook.event_types.is_empty() || webhook.event_types.iter().any(|name| name == event)
+        })
+    }
+
+    pub fn channel_name_limit(&self) -> u64 {
+        self.max_channel_name_length.unwrap_or(DEFAULT_MAX_CHANNEL_NAME_LENGTH)
+    }
+
+    pub fn event_name_limit(&self) -> u64 {
+        self.max_event_name_length.unwrap_or(DEFAULT_MAX_EVENT_NAME_LENGTH)
+    }
+
+    pub fn event_payload_limit_in_kb(&self) -> u64 {
+        self.max_event_payload_in_kb.unwrap_or(DEFAULT_MAX_EVENT_PAYLOAD_IN_KB)
+    }
+
+    pub fn event_channels_limit(&self) -> u64 {
+        self.max_event_channel_at_once.unwrap_or(DEFAULT_MAX_EVENT_CHANNEL_AT_ONCE)
+    }
+
+    pub fn event_batch_limit(&self) -> u64 {
+        self.max_event_batch_size.unwrap_or(DEFAULT_MAX_EVENT_BATCH_SIZE)
+    }
+
+    pub fn cache_ttl(&self) -> Duration {
+        Duration::from_secs(self.cache_ttl_in_seconds.unwrap_or(DEFAULT_CACHE_TTL_IN_SECONDS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webhook(event_types: &[&str]) -> WebhookConfig {
+        WebhookConfig {
+            url: "http://127.0.0.1/webhook".to_string(),
+            event_types: event_types.iter().map(|name| name.to_string()).collect(),
+            filter: WebhookFilter::default(),
+        }
+    }
+
+    #[test]
+    fn sends_webhooks_only_for_flagged_and_listed_events() {
+        let app = AppConfig {
+            has_member_added_webhooks: true,
+            ..Default::default()
+        };
+        assert!(!app.sends_webhook("member_added"));
+
+        let app = AppConfig {
+            webhooks: vec![webhook(&["channel_vacated"])],
+            ..Default::default()
+        };
+        assert!(!app.sends_webhook("channel_vacated"));
+
+        let app = AppConfig {
+            has_channel_vacated_webhooks: true,
+            has_channel_occupied_webhooks: true,
+            webhooks: vec![webhook(&["channel_vacated"])],
+            ..Default::default()
+        };
+        assert!(app.sends_webhook("channel_vacated"));
+        assert!(!app.sends_webhook("channel_occupied"));
+    }
+
+    #[test]
+    fn webhooks_without_event_types_get_every_flagged_event() {
+        let app = AppConfig {
+            has_client_event_webhooks: true,
+            has_cache_missed_webhooks: true,
+            webhooks: vec![webhook(&[])],
+            ..Default::default()
+        };
+        assert!(app.sends_webhook("client_event"));
+        assert!(app.sends_webhook("cache_miss"));
+        for event in ["channel_occupied", "channel_vacated", "member_added", "member_removed"] {
+            assert!(!app.sends_webhook(event), "{}", event);
+        }
+    }
+}
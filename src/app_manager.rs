@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use std::fmt;
+use actix_web::http::StatusCode;
+use crate::app::AppConfig;
+
+/// Why an app could not be resolved for an incoming connection or API call.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppError {
+    NotFound(String),
+    Disabled(String),
+}
+
+impl AppError {
+    /// The Pusher WebSocket error code sent in `pusher:error` before closing.
+    pub fn ws_code(&self) -> u16 {
+        match self {
+            AppError::NotFound(_) => 4001,
+            AppError::Disabled(_) => 4003,
+        }
+    }
+
+    /// The HTTP status code returned by the REST API.
+    pub fn http_status(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Disabled(_) => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::NotFound(app) => write!(f, "App {} does not exist", app),
+            AppError::Disabled(app) => write!(f, "App {} is disabled", app),
+        }
+    }
+}
+
+/// Keeps the apps loaded from the config file and resolves them by id or key.
+pub struct AppManager {
+    apps: HashMap<String, AppConfig>,
+    ids_by_key: HashMap<String, String>,
+}
+
+impl AppManager {
+    pub fn new(apps: Vec<AppConfig>) -> Self {
+        AppManager {
+            ids_by_key: apps.iter().map(|app| (app.key.clone(), app.id.clone())).collect(),
+            apps: apps.into_iter().map(|app| (app.id.clone(), app)).collect(),
+        }
+    }
+
+    /// Used by the HTTP API, where the path carries the app id.
+    pub fn find_by_id(&self, app_id: &str) -> Result<AppConfig, AppError> {
+        match self.apps.get(app_id) {
+            Some(app) if app.enabled => Ok(app.clone()),
+            Some(_) => Err(AppError::Disabled(app_id.to_string())),
+            None => Err(AppError::NotFound(app_id.to_string())),
+        }
+    }
+
+    /// Used by the WebSocket endpoint, where Pusher clients connect with the app key.
+    pub fn find_by_key(&self, key: &str) -> Result<AppConfig, AppError> {
+        match self.ids_by_key.get(key) {
+            Some(app_id) => self.find_by_id(app_id),
+            None => Err(AppError::NotFound(key.to_string())),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use futures_util::future::{ready, LocalBoxFuture};
+use crate::cache::CacheManager;
+
+struct Entry {
+    value: String,
+    expires_at: Instant,
+    /// Position in `Store::recency`.
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Store {
+    entries: HashMap<String, Entry>,
+    /// Keys by when they were last used, oldest first.
+    recency: BTreeMap<u64, String>,
+    clock: u64,
+}
+
+impl Store {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Returns the live entry for `key`, marking it as recently used.
+    fn touch(&mut self, key: &str, now: Instant) -> Option<&mut Entry> {
+        let expired = self.entries.get(key)?.expires_at <= now;
+        if expired {
+            self.remove(key);
+            return None;
+        }
+        let tick = self.tick();
+        let entry = self.entries.get_mut(key)?;
+        self.recency.remove(&entry.last_used);
+        self.recency.insert(tick, key.to_string());
+        entry.last_used = tick;
+        Some(entry)
+    }
+
+    fn insert(&mut self, key: &str, value: String, expires_at: Instant, max_entries: usize) {
+        self.remove(key);
+        let last_used = self.tick();
+        self.recency.insert(last_used, key.to_string());
+        self.entries.insert(key.to_string(), Entry { value, expires_at, last_used });
+        while self.entries.len() > max_entries {
+            let Some((_, oldest)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.last_used);
+        }
+    }
+}
+
+/// Cache kept in this process' memory, evicting the least recently used keys
+/// beyond `max_entries`.
+pub struct MemoryCacheManager {
+    store: Mutex<Store>,
+    max_entries: usize,
+}
+
+impl MemoryCacheManager {
+    pub fn new(max_entries: usize) -> Self {
+        MemoryCacheManager {
+            store: Mutex::new(Store::default()),
+            max_entries: max_entries.max(1),
+        }
+    }
+}
+
+impl CacheManager for MemoryCacheManager {
+    fn get(&self, key: &str) -> LocalBoxFuture<'_, Option<String>> {
+        let mut store = self.store.lock().unwrap();
+        let value = store.touch(key, Instant::now()).map(|entry| entry.value.clone());
+        Box::pin(ready(value))
+    }
+
+    fn set(&self, key: &str, value: String, ttl: Duration) -> LocalBoxFuture<'_, ()> {
+        let expires_at = Instant::now() + ttl;
+        self.store.lock().unwrap().insert(key, value, expires_at, self.max_entries);
+        Box::pin(ready(()))
+    }
+
+    fn increment(&self, key: &str, by: u64, ttl: Duration) -> LocalBoxFuture<'_, u64> {
+        let now = Instant::now();
+        let mut store = self.store.lock().unwrap();
+        let counter = match store.touch(key, now) {
+            Some(entry) => {
+                let counter = entry.value.parse::<u64>().unwrap_or(0).saturating_add(by);
+                entry.value = counter.to_string();
+                counter
+            }
+            None => {
+                store.insert(key, by.to_string(), now + ttl, self.max_entries);
+                by
+            }
+        };
+        Box::pin(ready(counter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    #[actix_web::test]
+    async fn returns_what_was_set() {
+        let cache = MemoryCacheManager::new(10);
+        assert_eq!(cache.get("a").await, None);
+        cache.set("a", "1".to_string(), TTL).await;
+        assert_eq!(cache.get("a").await.as_deref(), Some("1"));
+        cache.set("a", "2".to_string(), TTL).await;
+        assert_eq!(cache.get("a").await.as_deref(), Some("2"));
+    }
+
+    #[actix_web::test]
+    async fn expires_entries_after_their_ttl() {
+        let cache = MemoryCacheManager::new(10);
+        cache.set("short", "1".to_string(), Duration::from_millis(50)).await;
+        cache.set("long", "2".to_string(), TTL).await;
+        assert_eq!(cache.get("short").await.as_deref(), Some("1"));
+        actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(cache.get("short").await, None);
+        assert_eq!(cache.get("long").await.as_deref(), Some("2"));
+    }
+
+    #[actix_web::test]
+    async fn evicts_the_least_recently_used_entry() {
+        let cache = MemoryCacheManager::new(2);
+        cache.set("a", "1".to_string(), TTL).await;
+        cache.set("b", "2".to_string(), TTL).await;
+        // Reading `a` makes `b` the least recently used.
+        assert!(cache.get("a").await.is_some());
+        cache.set("c", "3".to_string(), TTL).await;
+        assert_eq!(cache.get("a").await.as_deref(), Some("1"));
+        assert_eq!(cache.get("b").await, None);
+        assert_eq!(cache.get("c").await.as_deref(), Some("3"));
+    }
+
+    #[actix_web::test]
+    async fn increments_until_the_counter_expires() {
+        let cache = MemoryCacheManager::new(10);
+        let ttl = Duration::from_millis(50);
+        assert_eq!(cache.increment("hits", 2, ttl).await, 2);
+        assert_eq!(cache.increment("hits", 3, ttl).await, 5);
+        actix_web::rt::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(cache.increment("hits", 1, ttl).await, 1);
+    }
+}
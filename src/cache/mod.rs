@@ -0,0 +1,19 @@
+pub mod memory_cache_manager;
+pub mod redis_cache_manager;
+
+use std::time::Duration;
+use futures_util::future::LocalBoxFuture;
+
+/// Key-value store with expiry behind cache channels and shared rate limits.
+/// The in-memory store only serves its own node; Redis is shared by the cluster.
+pub trait CacheManager: Send + Sync {
+    /// Returns the value of `key` unless it is missing or expired.
+    fn get(&self, key: &str) -> LocalBoxFuture<'_, Option<String>>;
+
+    /// Stores `value` under `key` for `ttl`, replacing any previous value.
+    fn set(&self, key: &str, value: String, ttl: Duration) -> LocalBoxFuture<'_, ()>;
+
+    /// Adds `by` to the counter `key` and returns its new value. A missing
+    /// counter starts at zero and expires `ttl` after it was created.
+    fn increment(&self, key: &str, by: u64, ttl: Duration) -> LocalBoxFuture<'_, u64>;
+}
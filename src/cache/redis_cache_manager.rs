@@ -0,0 +1,170 @@
+use std::time::Duration;
+use futures_util::future::LocalBoxFuture;
+use redis::aio::MultiplexedConnection;
+use redis::{AsyncConnectionConfig, RedisResult};
+use crate::cache::CacheManager;
+use crate::config::RedisCacheConfig;
+use crate::log::Log;
+
+/// How long a command may wait for Redis before it counts as failed.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Cache stored in Redis under `{prefix}:{key}`, shared by every node using it.
+/// A Redis failure is logged and treated as a miss, so it never blocks clients.
+pub struct RedisCacheManager {
+    prefix: String,
+    connection: MultiplexedConnection,
+}
+
+impl RedisCacheManager {
+    pub async fn connect(config: &RedisCacheConfig) -> RedisResult<Self> {
+        let client = redis::Client::open(config.url.as_str())?;
+        let connection_config = AsyncConnectionConfig::new()
+            .set_connection_timeout(RESPONSE_TIMEOUT)
+            .set_response_timeout(RESPONSE_TIMEOUT);
+        let connection = client.get_multiplexed_async_connection_with_config(&connection_config).await?;
+        Log::info_title(format!("Using the Redis cache at {}", config.url).as_str());
+        Ok(RedisCacheManager {
+            prefix: config.prefix.clone(),
+            connection,
+        })
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}:{}", self.prefix, key)
+    }
+}
+
+fn log_error(command: &str, key: &str, e: redis::RedisError) {
+    Log::error_title(format!("Redis cache {} of {} failed: {}", command, key, e).as_str());
+}
+
+impl CacheManager for RedisCacheManager {
+    fn get(&self, key: &str) -> LocalBoxFuture<'_, Option<String>> {
+        let key = self.key(key);
+        let mut connection = self.connection.clone();
+        Box::pin(async move {
+            redis::cmd("GET")
+                .arg(&key)
+                .query_async(&mut connection)
+                .await
+                .unwrap_or_else(|e| {
+                    log_error("GET", &key, e);
+                    None
+                })
+        })
+    }
+
+    fn set(&self, key: &str, value: String, ttl: Duration) -> LocalBoxFuture<'_, ()> {
+        let key = self.key(key);
+        let mut connection = self.connection.clone();
+        Box::pin(async move {
+            let result: RedisResult<()> = redis::cmd("SET")
+                .arg(&key)
+                .arg(value)
+                .arg("PX")
+                .arg(ttl.as_millis() as u64)
+                .query_async(&mut connection)
+                .await;
+            if let Err(e) = result {
+                log_error("SET", &key, e);
+            }
+        })
+    }
+
+    fn increment(&self, key: &str, by: u64, ttl: Duration) -> LocalBoxFuture<'_, u64> {
+        let key = self.key(key);
+        let mut connection = self.connection.clone();
+        Box::pin(async move {
+            let counter: u64 = match redis::cmd("INCRBY").arg(&key).arg(by).query_async(&mut connection).await {
+                Ok(counter) => counter,
+                Err(e) => {
+                    log_error("INCRBY", &key, e);
+                    return 0;
+                }
+            };
+            // Only the increment that created the counter starts its expiry.
+            if counter == by {
+                let result: RedisResult<()> = redis::cmd("PEXPIRE")
+                    .arg(&key)
+                    .arg(ttl.as_millis() as u64)
+                    .query_async(&mut connection)
+                    .await;
+                if let Err(e) = result {
+                    log_error("PEXPIRE", &key, e);
+                }
+            }
+            counter
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+
+    // Run against a redis-server with `REDIS_URL=redis://127.0.0.1:6379 cargo test -- --ignored`.
+
+    async fn cache() -> RedisCacheManager {
+        let config = RedisCacheConfig {
+            url: std::env::var("REDIS_URL").expect("REDIS_URL points at a redis-server"),
+            // Keys of their own, so concurrent tests on the same server stay apart.
+            prefix: format!("test-{}", utils::generate_node_id()),
+        };
+        RedisCacheManager::connect(&config).await.unwrap()
+    }
+
+    async fn sleep(duration: Duration) {
+        actix_web::rt::time::sleep(duration).await;
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a redis-server at REDIS_URL"]
+    async fn expires_entries_after_their_ttl() {
+        let cache = cache().await;
+        assert_eq!(cache.get("a").await, None);
+        cache.set("a", "1".to_string(), Duration::from_millis(200)).await;
+        cache.set("b", "2".to_string(), Duration::from_secs(60)).await;
+        assert_eq!(cache.get("a").await.as_deref(), Some("1"));
+        sleep(Duration::from_millis(400)).await;
+        assert_eq!(cache.get("a").await, None);
+        assert_eq!(cache.get("b").await.as_deref(), Some("2"));
+    }
+
+    #[actix_web::test]
+    #[ignore = "needs a redis-server at REDIS_URL"]
+    async fn increments_until_the_counter_expires() {
+        let cache = cache().await;
+        let ttl = Duration::from_millis(200);
+        assert_eq!(cache.increment("hits", 2, ttl).await, 2);
+        assert_eq!(cache.increment("hits", 3, ttl).await, 5);
+        sleep(Duration::from_millis(400)).await;
+        assert_eq!(cache.increment("hits", 1, ttl).await, 1);
+    }
+
+    #[actix_web::test]
+    async fn a_silent_server_is_a_miss_not_a_hang() {
+        // Accepts connections but never answers.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}", listener.local_addr().unwrap());
+        actix_web::rt::spawn(async move {
+            let mut connections = vec![];
+            while let Ok((connection, _)) = listener.accept().await {
+                connections.push(connection);
+            }
+        });
+        let config = RedisCacheConfig {
+            url,
+            prefix: "test".to_string(),
+        };
+        let lookup = async {
+            match RedisCacheManager::connect(&config).await {
+                Ok(cache) => cache.get("a").await,
+                Err(_) => None,
+            }
+        };
+        let result = tokio::time::timeout(RESPONSE_TIMEOUT * 3, lookup).await;
+        assert_eq!(result.ok(), Some(None));
+    }
+}
@@ -1 +1,34 @@
-pub mod public_channel_manager;
\ No newline at end of file
+pub mod private_channel_manager;
+pub mod presence_channel_manager;
+
+/// The kind of channel, derived from its name prefix.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelType {
+    Public,
+    Private,
+    /// End-to-end encrypted: subscribers authenticate as on private channels,
+    /// and the server only relays the ciphertext.
+    PrivateEncrypted,
+    Presence,
+}
+
+impl ChannelType {
+    pub fn from_name(channel: &str) -> Self {
+        if channel.starts_with("presence-") {
+            ChannelType::Presence
+        } else if channel.starts_with("private-encrypted-") {
+            ChannelType::PrivateEncrypted
+        } else if channel.starts_with("private-") {
+            ChannelType::Private
+        } else {
+            ChannelType::Public
+        }
+    }
+}
+
+/// Cache channels remember their last event and replay it to new subscribers.
+pub fn is_cache_channel(channel: &str) -> bool {
+    ["cache-", "private-cache-", "private-encrypted-cache-", "presence-cache-"]
+        .iter()
+        .any(|prefix| channel.starts_with(prefix))
+}
@@ -0,0 +1,77 @@
+use serde_json::Value;
+use crate::app::AppConfig;
+use crate::channel_managers::private_channel_manager::PrivateChannelManager;
+
+/// A user as described by the `channel_data` of a presence subscription.
+#[derive(Debug, Clone)]
+pub struct PresenceMember {
+    pub user_id: String,
+    pub user_info: Value,
+}
+
+pub struct PresenceChannelManager;
+
+impl PresenceChannelManager {
+    /// Presence subscriptions sign `<socket_id>:<channel>:<channel_data>`.
+    pub fn signature_is_valid(
+        app: &AppConfig,
+        socket_id: &str,
+        channel: &str,
+        channel_data: &str,
+        auth: Option<&str>,
+    ) -> bool {
+        PrivateChannelManager::verify(app, &format!("{}:{}:{}", socket_id, channel, channel_data), auth)
+    }
+
+    /// Reads `user_id` (a string or a number) and the optional `user_info` from `channel_data`.
+    pub fn parse_member(channel_data: &str) -> Option<PresenceMember> {
+        let channel_data: Value = serde_json::from_str(channel_data).ok()?;
+        let user_id = match channel_data.get("user_id")? {
+            Value::String(user_id) => user_id.clone(),
+            Value::Number(user_id) => user_id.to_string(),
+            _ => return None,
+        };
+        Some(PresenceMember {
+            user_id,
+            user_info: channel_data.get("user_info").cloned().unwrap_or(Value::Null),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app() -> AppConfig {
+        AppConfig {
+            key: "278d425bdf160c739803".to_string(),
+            secret: Some("7ad3773142a6692b25b8".to_string()),
+            ..Default::default()
+        }
+    }
+
+    const CHANNEL_DATA: &str = r#"{"user_id":10,"user_info":{"name":"Mr. Channel"}}"#;
+    const AUTH: &str = "278d425bdf160c739803:fc92c2263fd5b72721e20dd1a06a900e6b3c4fbfac6cb9b098d68eb7911498a2";
+
+    #[test]
+    fn accepts_a_signature_over_the_channel_data() {
+        assert!(PresenceChannelManager::signature_is_valid(&app(), "1234.1234", "presence-foobar", CHANNEL_DATA, Some(AUTH)));
+    }
+
+    #[test]
+    fn rejects_altered_channel_data() {
+        let channel_data = CHANNEL_DATA.replace("10", "11");
+        assert!(!PresenceChannelManager::signature_is_valid(&app(), "1234.1234", "presence-foobar", &channel_data, Some(AUTH)));
+        assert!(!PresenceChannelManager::signature_is_valid(&app(), "1234.1234", "presence-foobaz", CHANNEL_DATA, Some(AUTH)));
+        assert!(!PresenceChannelManager::signature_is_valid(&app(), "1234.1234", "presence-foobar", CHANNEL_DATA, None));
+    }
+
+    #[test]
+    fn reads_numeric_and_string_user_ids() {
+        let member = PresenceChannelManager::parse_member(CHANNEL_DATA).unwrap();
+        assert_eq!(member.user_id, "10");
+        assert_eq!(member.user_info, serde_json::json!({"name": "Mr. Channel"}));
+        assert_eq!(PresenceChannelManager::parse_member(r#"{"user_id":"ann"}"#).unwrap().user_id, "ann");
+        assert!(PresenceChannelManager::parse_member(r#"{"user_info":{}}"#).is_none());
+    }
+}
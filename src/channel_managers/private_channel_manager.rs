@@ -0,0 +1,63 @@
+use crate::app::AppConfig;
+use crate::utils;
+
+pub struct PrivateChannelManager;
+
+impl PrivateChannelManager {
+    /// Checks the `auth` field of a subscription, which has the form
+    /// `<app key>:<hex HMAC-SHA256 of "<socket_id>:<channel>">`.
+    pub fn signature_is_valid(app: &AppConfig, socket_id: &str, channel: &str, auth: Option<&str>) -> bool {
+        Self::verify(app, &format!("{}:{}", socket_id, channel), auth)
+    }
+
+    pub(crate) fn verify(app: &AppConfig, string_to_sign: &str, auth: Option<&str>) -> bool {
+        let (Some(auth), Some(secret)) = (auth, app.secret.as_deref()) else {
+            return false;
+        };
+        match auth.split_once(':') {
+            Some((key, signature)) => key == app.key && utils::verify_signature(secret, string_to_sign, signature),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example from Pusher's channel authorization documentation.
+    fn app() -> AppConfig {
+        AppConfig {
+            key: "278d425bdf160c739803".to_string(),
+            secret: Some("7ad3773142a6692b25b8".to_string()),
+            ..Default::default()
+        }
+    }
+
+    const AUTH: &str = "278d425bdf160c739803:58df8b0c36d6982b82c3ecf6b4662e34fe8c25bba48f5369f135bf843651c3a4";
+
+    #[test]
+    fn accepts_the_documented_signature() {
+        assert!(PrivateChannelManager::signature_is_valid(&app(), "1234.1234", "private-foobar", Some(AUTH)));
+    }
+
+    #[test]
+    fn rejects_signatures_for_another_socket_or_channel() {
+        assert!(!PrivateChannelManager::signature_is_valid(&app(), "1234.1235", "private-foobar", Some(AUTH)));
+        assert!(!PrivateChannelManager::signature_is_valid(&app(), "1234.1234", "private-foobaz", Some(AUTH)));
+    }
+
+    #[test]
+    fn rejects_malformed_or_missing_auth() {
+        let (_, signature) = AUTH.split_once(':').unwrap();
+        let other_key = format!("0000000000000000000:{}", signature);
+        for auth in [None, Some(signature), Some(other_key.as_str())] {
+            assert!(!PrivateChannelManager::signature_is_valid(&app(), "1234.1234", "private-foobar", auth), "{:?}", auth);
+        }
+        let app = AppConfig {
+            secret: None,
+            ..app()
+        };
+        assert!(!PrivateChannelManager::signature_is_valid(&app, "1234.1234", "private-foobar", Some(AUTH)));
+    }
+}
@@ -0,0 +1,151 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use crate::app::AppConfig;
+
+fn default_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_port() -> u16 {
+    6001
+}
+
+fn default_workers() -> usize {
+    32
+}
+
+fn default_activity_timeout() -> u64 {
+    120
+}
+
+fn default_pong_timeout() -> u64 {
+    30
+}
+
+fn default_redis_url() -> String {
+    "redis://127.0.0.1:6379".to_string()
+}
+
+fn default_cluster_prefix() -> String {
+    "sockudo".to_string()
+}
+
+fn default_heartbeat_interval() -> u64 {
+    5
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RedisAdapterConfig {
+    #[serde(default = "default_redis_url")]
+    pub url: String,
+    /// Prefix of the pub/sub channels, so several clusters can share a Redis.
+    #[serde(default = "default_cluster_prefix")]
+    pub prefix: String,
+}
+
+fn default_nats_url() -> String {
+    "nats://127.0.0.1:4222".to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NatsAdapterConfig {
+    #[serde(default = "default_nats_url")]
+    pub url: String,
+    /// Prefix of the subjects, so several clusters can share a NATS server.
+    #[serde(default = "default_cluster_prefix")]
+    pub prefix: String,
+    /// Seconds between the announcements a node makes on `{prefix}.nodes`.
+    #[serde(default = "default_heartbeat_interval")]
+    pub heartbeat_interval: u64,
+}
+
+/// How this node reaches the other nodes of the cluster, if any.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(tag = "driver", rename_all = "lowercase")]
+pub enum AdapterConfig {
+    #[default]
+    Local,
+    Redis(RedisAdapterConfig),
+    Nats(NatsAdapterConfig),
+}
+
+fn default_max_cache_entries() -> usize {
+    10_000
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MemoryCacheConfig {
+    /// Least recently used keys are evicted beyond this many entries.
+    #[serde(default = "default_max_cache_entries")]
+    pub max_entries: usize,
+}
+
+impl Default for MemoryCacheConfig {
+    fn default() -> Self {
+        MemoryCacheConfig {
+            max_entries: default_max_cache_entries(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RedisCacheConfig {
+    #[serde(default = "default_redis_url")]
+    pub url: String,
+    /// Prefix of the keys, so several clusters can share a Redis.
+    #[serde(default = "default_cluster_prefix")]
+    pub prefix: String,
+}
+
+/// Where cached channel events and shared rate limit counters are kept.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "driver", rename_all = "lowercase")]
+pub enum CacheConfig {
+    Memory(MemoryCacheConfig),
+    Redis(RedisCacheConfig),
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig::Memory(MemoryCacheConfig::default())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServerConfig {
+    #[serde(default = "default_host")]
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+    /// Seconds without any frame from a client before the server pings it.
+    #[serde(default = "default_activity_timeout")]
+    pub activity_timeout: u64,
+    /// Seconds to wait for a reply to that ping before closing with 4201.
+    #[serde(default = "default_pong_timeout")]
+    pub pong_timeout: u64,
+    /// Server-wide cap on open connections across all apps.
+    pub max_connections: Option<u64>,
+    #[serde(default)]
+    pub adapter: AdapterConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub apps: Vec<AppConfig>,
+}
+
+impl ServerConfig {
+    /// Loads the server config from a JSON file, or TOML when the path ends in `.toml`.
+    pub fn load(path: &str) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let is_toml = Path::new(path).extension().is_some_and(|ext| ext == "toml");
+        if is_toml {
+            toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        } else {
+            serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        }
+    }
+}
@@ -1,3 +1,5 @@
+#![cfg_attr(all(test, sockudo_bench), feature(test))]
+
 mod server;
 mod ws_message;
 mod utils;
@@ -5,28 +7,68 @@ mod message;
 mod log;
 mod namespace;
 mod adapter;
+mod cache;
 mod channel_managers;
 mod app;
+mod app_manager;
 mod config;
+mod middleware;
+mod pusher_error;
+mod validation;
+mod rate_limiter;
+mod webhook;
 
 use std::collections::HashMap;
-use actix::{Actor, Addr, AsyncContext, StreamHandler};
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use actix::{Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, StreamHandler, WrapFuture};
 use actix_web::{get, post, web, App, HttpResponse, HttpServer, Responder, HttpRequest, Error};
 use actix_web::web::Path;
 use actix_web_actors::ws;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use crate::adapter::local_adapter::{GetNamespace, LocalAdapter, SendMessage};
+use crate::adapter::local_adapter::{AddSocket, GetChannelInfo, GetChannelUsers, GetChannels, LocalAdapter, RemoveSocket, SendMessage, SetHorizontalAdapter};
+use crate::adapter::nats_adapter::NatsAdapter;
+use crate::adapter::redis_adapter::RedisAdapter;
+use crate::adapter::HorizontalAdapter;
+use crate::app::AppConfig;
+use crate::app_manager::AppManager;
+use crate::config::{AdapterConfig, CacheConfig, ServerConfig};
 use crate::log::Log;
-use crate::message::PusherApiMessage;
-use crate::namespace::{Namespace};
+use crate::channel_managers::ChannelType;
+use crate::message::{PusherApiMessage, PusherApiMessageInfo, PusherBatchMessage};
+use crate::namespace::ChannelInfo;
+use crate::middleware::pusher_auth::PusherAuth;
+use crate::middleware::rate_limit::RateLimit;
+use crate::pusher_error::PusherError;
+use crate::validation::ValidationError;
+use crate::webhook::webhook_sender::WebhookSender;
+use crate::cache::memory_cache_manager::MemoryCacheManager;
+use crate::cache::redis_cache_manager::RedisCacheManager;
+use crate::cache::CacheManager;
+use crate::rate_limiter::cache_rate_limiter::CacheRateLimiter;
+use crate::rate_limiter::local_rate_limiter::LocalRateLimiter;
+use crate::rate_limiter::RateLimiter;
+
+/// How often connections are checked for inactivity.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 
 /// Define HTTP actor
 #[derive(Debug)]
 struct WS {
     id: Option<String>,
-    app_id: Option<String>,
+    app: AppConfig,
     local_adapter: Addr<LocalAdapter>,
+    /// Channels this socket subscribed to, with its `user_id` on presence channels.
+    channels: HashMap<String, Option<String>>,
+    /// Start of the current one second window and the client events sent in it.
+    client_events: Option<(Instant, u64)>,
+    activity_timeout: Duration,
+    pong_timeout: Duration,
+    last_activity: Instant,
+    /// When the server sent `pusher:ping` to an idle client that has not answered yet.
+    ping_sent_at: Option<Instant>,
 }
 
 impl Actor for WS {
@@ -36,53 +78,128 @@ impl Actor for WS {
         Log::websocket_title("Connection opened");
         let id = utils::generate_socket_id();
         self.id = Some(id.clone());
-        let broadcast_message = json!({
-            "event": "pusher:connection_established",
-            "data": {
-                "socket_id": id,
-                "activity_timeout": 120,
-            },
+        let add_socket = self.local_adapter.send(AddSocket {
+            app_id: self.app.id.clone(),
+            socket_id: id.clone(),
+            socket_addr: ctx.address().recipient(),
         });
-        println!("{:?}", broadcast_message);
-        ctx.text(broadcast_message.to_string());
+        // Hold back client frames until the connection is accepted.
+        ctx.wait(add_socket.into_actor(self).map(move |result, act, ctx| {
+            match result.unwrap_or(Err(PusherError::OverCapacity)) {
+                Ok(()) => {
+                    let broadcast_message = json!({
+                        "event": "pusher:connection_established",
+                        "data": {
+                            "socket_id": id,
+                            "activity_timeout": act.activity_timeout.as_secs(),
+                        },
+                    });
+                    Log::websocket_title(format!("Connection established: {}", broadcast_message).as_str());
+                    ctx.text(broadcast_message.to_string());
+                    act.heartbeat(ctx);
+                }
+                Err(error) => {
+                    // The socket was never registered, so there is nothing to remove on stop.
+                    act.id = None;
+                    act.send_error(ctx, error);
+                }
+            }
+        }));
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        Log::websocket_title("Connection closed");
+        if let Some(id) = self.id.take() {
+            self.local_adapter.do_send(RemoveSocket {
+                app_id: self.app.id.clone(),
+                socket_id: id,
+            });
+        }
     }
 }
 
 impl WS {
-    pub fn new(local_adapter: Addr<LocalAdapter>, app_id: String) -> Self {
+    pub fn new(local_adapter: Addr<LocalAdapter>, app: AppConfig, config: &ServerConfig) -> Self {
         WS {
             id: None,
-            app_id: Some(app_id),
+            app,
             local_adapter,
+            channels: HashMap::new(),
+            client_events: None,
+            activity_timeout: Duration::from_secs(config.activity_timeout),
+            pong_timeout: Duration::from_secs(config.pong_timeout),
+            last_activity: Instant::now(),
+            ping_sent_at: None,
         }
     }
+
+    /// Pings clients that stay idle for `activity_timeout` and closes the
+    /// connection with 4201 when the ping goes unanswered for `pong_timeout`.
+    fn heartbeat(&self, ctx: &mut <Self as Actor>::Context) {
+        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+            match act.ping_sent_at {
+                Some(ping_sent_at) if ping_sent_at.elapsed() >= act.pong_timeout => {
+                    act.send_error(ctx, PusherError::PongNotReceived);
+                }
+                None if act.last_activity.elapsed() >= act.activity_timeout => {
+                    ctx.text(json!({ "event": "pusher:ping", "data": {} }).to_string());
+                    act.ping_sent_at = Some(Instant::now());
+                }
+                _ => {}
+            }
+        });
+    }
+}
+
+/// Actor for connections refused during the handshake: it sends the Pusher
+/// error frame and closes the socket with the same code.
+struct RejectedConnection {
+    error: PusherError,
+}
+
+impl Actor for RejectedConnection {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        Log::warning_title(format!("Connection rejected: {}", self.error).as_str());
+        ctx.text(self.error.to_frame().to_string());
+        ctx.close(Some(self.error.close_reason()));
+        ctx.stop();
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for RejectedConnection {
+    fn handle(&mut self, _: Result<ws::Message, ws::ProtocolError>, _: &mut Self::Context) {}
 }
 
 /// Handler for ws::Message
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WS {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        if msg.is_ok() {
+            self.last_activity = Instant::now();
+            self.ping_sent_at = None;
+        }
         match msg {
             Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            // Frames carry `auth` and `channel_data`, so they are not logged.
             Ok(ws::Message::Text(text)) => {
-                println!("Received a text message: {}", text);
-                let message = ws_message::OnMessage {
-                    message: serde_json::from_str(text.to_string().as_str()).unwrap(),
-                };
-                ctx.address().do_send(message);
+                match ws_message::parse_frame(&text) {
+                    Ok(message) => ctx.address().do_send(ws_message::OnMessage { message }),
+                    Err(error) => self.send_error(ctx, error),
+                }
             }
-            Ok(ws::Message::Binary(bin)) => {
-                ctx.binary(bin);
-                println!("Received a binary message")
+            Ok(ws::Message::Binary(_)) => {
+                self.send_error(ctx, PusherError::InvalidMessage("binary frames are not supported".to_string()));
             }
-            Ok(ws::Message::Pong(_)) => println!("Received a pong"),
+            Ok(ws::Message::Pong(_)) | Ok(ws::Message::Continuation(_)) | Ok(ws::Message::Nop) => {}
             Ok(ws::Message::Close(reason)) => {
-                println!("Received a close message: {:?}", reason);
+                Log::websocket_title(format!("Received a close message: {:?}", reason).as_str());
                 ctx.close(reason);
+                ctx.stop();
             }
-            Ok(ws::Message::Continuation(_)) => println!("Received a continuation message"),
-            Ok(ws::Message::Nop) => println!("Received a nop message"),
             Err(e) => {
-                println!("An error occurred: {:?}", e);
+                Log::error_title(format!("Websocket protocol error: {}", e).as_str());
+                ctx.stop();
             }
         }
     }
@@ -90,50 +207,284 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WS {
 
 #[derive(Debug, Serialize, Deserialize)]
 struct PusherQuery {
-    protocol: String,
-    client: String,
-    version: String,
-    flash: String
+    protocol: Option<String>,
+    client: Option<String>,
+    version: Option<String>,
+    flash: Option<String>,
 }
 
-#[get("/app/{app_id}")]
-async fn ws_handler(app_id: Path<String>,
+impl PusherQuery {
+    /// Accepts protocol versions 5 to 7; clients that omit it are let through.
+    fn check_protocol(&self) -> Result<(), PusherError> {
+        let Some(protocol) = self.protocol.as_deref() else {
+            return Ok(());
+        };
+        match protocol.parse::<u8>() {
+            Ok(version) if (5..=7).contains(&version) => Ok(()),
+            Ok(_) => Err(PusherError::UnsupportedProtocolVersion(protocol.to_string())),
+            Err(_) => Err(PusherError::InvalidVersionString(protocol.to_string())),
+        }
+    }
+}
+
+#[get("/app/{app_key}")]
+async fn ws_handler(app_key: Path<String>,
                     query: web::Query<PusherQuery>,
-                    req: HttpRequest, 
-                    stream: web::Payload, 
+                    req: HttpRequest,
+                    stream: web::Payload,
                     local_adapter: web::Data<Addr<LocalAdapter>>,
-                    
+                    app_manager: web::Data<AppManager>,
+                    config: web::Data<ServerConfig>,
 ) -> Result<HttpResponse, Error> {
-    let app_id = app_id.into_inner();
-    let local_adapter = local_adapter.get_ref().clone();
-    let resp = ws::start(WS::new(local_adapter, app_id), &req, stream);
-    resp
+    let app = query
+        .check_protocol()
+        .and_then(|_| app_manager.find_by_key(&app_key).map_err(PusherError::from));
+    match app {
+        Ok(app) => ws::start(WS::new(local_adapter.get_ref().clone(), app, &config), &req, stream),
+        Err(error) => ws::start(RejectedConnection { error }, &req, stream),
+    }
 }
 
-#[post("/apps/{app_id}/events")]
-async fn pusher_event(app_id: Path<String>, info: web::Json<PusherApiMessage>, local_adapter: web::Data<Addr<LocalAdapter>>) -> impl Responder {
+// The last `wrap` is the outermost layer, so unsigned requests are refused
+// before they can spend the app's rate limit.
+#[post("/apps/{app_id}/events", wrap = "RateLimit::backend_events()", wrap = "PusherAuth")]
+async fn pusher_event(app_id: Path<String>,
+                      info: web::Json<PusherApiMessage>,
+                      local_adapter: web::Data<Addr<LocalAdapter>>,
+                      app_manager: web::Data<AppManager>,
+) -> impl Responder {
+    let app = match app_manager.find_by_id(&app_id) {
+        Ok(app) => app,
+        Err(e) => return utils::error_response(e.http_status(), &e.to_string()),
+    };
     let message = info.into_inner();
+    let validated = validation::validate_api_message(&app, &message).and_then(|_| validation::validate_info(&message));
+    if let Err(e) = validated {
+        return utils::error_response(e.http_status(), &e.to_string());
+    }
+    let info = message.info.unwrap_or_default();
+    let channels = message.channels();
     local_adapter.do_send(SendMessage {
-        app_id: app_id.into_inner(),
+        app_id: app.id.clone(),
         message,
     });
-    HttpResponse::Ok().body("Event sent")
+    if info.is_empty() {
+        return HttpResponse::Ok().json(json!({}));
+    }
+    // Looked up after the send, so the counts reflect the state the event was published into.
+    let mut channels_info = HashMap::with_capacity(channels.len());
+    for channel in channels {
+        let channel_info = local_adapter.send(GetChannelInfo {
+            app_id: app.id.clone(),
+            channel: channel.clone(),
+            info,
+        }).await.unwrap_or_default();
+        channels_info.insert(channel, channel_info);
+    }
+    HttpResponse::Ok().json(json!({ "channels": channels_info }))
+}
+
+#[post("/apps/{app_id}/batch_events", wrap = "RateLimit::batch_events()", wrap = "PusherAuth")]
+async fn pusher_batch_events(app_id: Path<String>,
+                             body: web::Json<PusherBatchMessage>,
+                             local_adapter: web::Data<Addr<LocalAdapter>>,
+                             app_manager: web::Data<AppManager>,
+) -> impl Responder {
+    let app = match app_manager.find_by_id(&app_id) {
+        Ok(app) => app,
+        Err(e) => return utils::error_response(e.http_status(), &e.to_string()),
+    };
+    let batch = body.into_inner().batch;
+    if let Err(e) = validation::validate_batch(&app, &batch) {
+        return utils::error_response(e.http_status(), &e.to_string());
+    }
+    // Keep what the info lookups need; the adapter's mailbox runs them after the sends.
+    let requested: Vec<_> = batch
+        .iter()
+        .map(|message| (message.channels().remove(0), message.info.unwrap_or_default()))
+        .collect();
+    for message in batch {
+        local_adapter.do_send(SendMessage {
+            app_id: app.id.clone(),
+            message,
+        });
+    }
+    if requested.iter().all(|(_, info)| info.is_empty()) {
+        return HttpResponse::Ok().json(json!({}));
+    }
+    let mut infos = Vec::with_capacity(requested.len());
+    for (channel, info) in requested {
+        let channel_info = if info.is_empty() {
+            ChannelInfo::default()
+        } else {
+            local_adapter.send(GetChannelInfo {
+                app_id: app.id.clone(),
+                channel,
+                info,
+            }).await.unwrap_or_default()
+        };
+        infos.push(channel_info);
+    }
+    HttpResponse::Ok().json(json!({ "batch": infos }))
+}
+
+#[derive(Deserialize)]
+struct ChannelsQuery {
+    filter_by_prefix: Option<String>,
+    info: Option<PusherApiMessageInfo>,
+}
+
+#[get("/apps/{app_id}/channels", wrap = "RateLimit::read_requests()", wrap = "PusherAuth")]
+async fn get_channels(app_id: Path<String>,
+                      query: web::Query<ChannelsQuery>,
+                      local_adapter: web::Data<Addr<LocalAdapter>>,
+                      app_manager: web::Data<AppManager>,
+) -> impl Responder {
+    let app = match app_manager.find_by_id(&app_id) {
+        Ok(app) => app,
+        Err(e) => return utils::error_response(e.http_status(), &e.to_string()),
+    };
+    let ChannelsQuery { filter_by_prefix, info } = query.into_inner();
+    let info = info.unwrap_or_default();
+    if info.user_count && !filter_by_prefix.as_deref().is_some_and(|prefix| prefix.starts_with("presence-")) {
+        let e = ValidationError::UserCountRequiresPresence;
+        return utils::error_response(e.http_status(), &e.to_string());
+    }
+    let channels = local_adapter.send(GetChannels {
+        app_id: app.id,
+        prefix: filter_by_prefix,
+        info,
+    }).await.unwrap_or_default();
+    HttpResponse::Ok().json(json!({ "channels": channels }))
+}
+
+#[get("/apps/{app_id}/channels/{channel_name}", wrap = "RateLimit::read_requests()", wrap = "PusherAuth")]
+async fn get_channel(path: Path<(String, String)>,
+                     query: web::Query<ChannelsQuery>,
+                     local_adapter: web::Data<Addr<LocalAdapter>>,
+                     app_manager: web::Data<AppManager>,
+) -> impl Responder {
+    let (app_id, channel_name) = path.into_inner();
+    let app = match app_manager.find_by_id(&app_id) {
+        Ok(app) => app,
+        Err(e) => return utils::error_response(e.http_status(), &e.to_string()),
+    };
+    let info = query.into_inner().info.unwrap_or_default();
+    if let Err(e) = validation::validate_channel_name(&app, &channel_name) {
+        return utils::error_response(e.http_status(), &e.to_string());
+    }
+    if info.user_count && ChannelType::from_name(&channel_name) != ChannelType::Presence {
+        let e = ValidationError::UserCountRequiresPresence;
+        return utils::error_response(e.http_status(), &e.to_string());
+    }
+    // `occupied` is always reported, so the subscription count is always looked up.
+    let mut channel_info = local_adapter.send(GetChannelInfo {
+        app_id: app.id,
+        channel: channel_name,
+        info: PusherApiMessageInfo { subscription_count: true, ..info },
+    }).await.unwrap_or_default();
+    channel_info.occupied = Some(channel_info.subscription_count.unwrap_or(0) > 0);
+    if !info.subscription_count {
+        channel_info.subscription_count = None;
+    }
+    HttpResponse::Ok().json(channel_info)
+}
+
+#[get("/apps/{app_id}/channels/{channel_name}/users", wrap = "RateLimit::read_requests()", wrap = "PusherAuth")]
+async fn get_channel_users(path: Path<(String, String)>,
+                           local_adapter: web::Data<Addr<LocalAdapter>>,
+                           app_manager: web::Data<AppManager>,
+) -> impl Responder {
+    let (app_id, channel_name) = path.into_inner();
+    let app = match app_manager.find_by_id(&app_id) {
+        Ok(app) => app,
+        Err(e) => return utils::error_response(e.http_status(), &e.to_string()),
+    };
+    if let Err(e) = validation::validate_channel_name(&app, &channel_name) {
+        return utils::error_response(e.http_status(), &e.to_string());
+    }
+    if ChannelType::from_name(&channel_name) != ChannelType::Presence {
+        let e = ValidationError::NotPresenceChannel(channel_name);
+        return utils::error_response(e.http_status(), &e.to_string());
+    }
+    let users = local_adapter.send(GetChannelUsers {
+        app_id: app.id,
+        channel: channel_name,
+    }).await.unwrap_or_default();
+    let users: Vec<_> = users.into_iter().map(|id| json!({ "id": id })).collect();
+    HttpResponse::Ok().json(json!({ "users": users }))
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let local_adapter = LocalAdapter {
-        namespaces: HashMap::new(),
-    }.start();
+    let config_path = std::env::args().nth(1).unwrap_or_else(|| "config.json".to_string());
+    let mut config = ServerConfig::load(&config_path).map_err(|e| {
+        Log::error_title(format!("Could not load config from {}: {}", config_path, e).as_str());
+        e
+    })?;
+    Log::info_title(format!("Loaded {} app(s) from {}", config.apps.len(), config_path).as_str());
+    let app_manager = web::Data::new(AppManager::new(std::mem::take(&mut config.apps)));
+    let config = web::Data::new(config);
+    let (host, port, workers) = (config.host.clone(), config.port, config.workers);
+    let webhook_sender = WebhookSender::new(app_manager.clone().into_inner()).start();
+    let cache: Arc<dyn CacheManager> = match &config.cache {
+        CacheConfig::Memory(memory_config) => Arc::new(MemoryCacheManager::new(memory_config.max_entries)),
+        CacheConfig::Redis(redis_config) => {
+            let redis_cache = RedisCacheManager::connect(redis_config).await.map_err(|e| {
+                Log::error_title(format!("Could not connect to Redis at {}: {}", redis_config.url, e).as_str());
+                io::Error::new(io::ErrorKind::ConnectionRefused, e)
+            })?;
+            Arc::new(redis_cache)
+        }
+    };
+    let local_adapter = LocalAdapter::new(
+        app_manager.clone().into_inner(),
+        config.max_connections,
+        webhook_sender,
+        cache.clone(),
+    ).start();
+    let horizontal: Option<Box<dyn HorizontalAdapter>> = match &config.adapter {
+        AdapterConfig::Local => None,
+        AdapterConfig::Redis(redis_config) => {
+            let redis_adapter = RedisAdapter::connect(redis_config, local_adapter.clone()).await.map_err(|e| {
+                Log::error_title(format!("Could not connect to Redis at {}: {}", redis_config.url, e).as_str());
+                io::Error::new(io::ErrorKind::ConnectionRefused, e)
+            })?;
+            Some(Box::new(redis_adapter))
+        }
+        AdapterConfig::Nats(nats_config) => {
+            let nats_adapter = NatsAdapter::connect(nats_config, local_adapter.clone()).await.map_err(|e| {
+                Log::error_title(format!("Could not connect to NATS at {}: {}", nats_config.url, e).as_str());
+                io::Error::new(io::ErrorKind::ConnectionRefused, e.to_string())
+            })?;
+            Some(Box::new(nats_adapter))
+        }
+    };
+    if let Some(horizontal) = horizontal {
+        local_adapter.send(SetHorizontalAdapter(horizontal)).await.expect("the local adapter is running");
+    }
+    let rate_limiter: Arc<dyn RateLimiter> = match &config.cache {
+        CacheConfig::Memory(_) => Arc::new(LocalRateLimiter::new()),
+        // Counters in a shared cache limit the app across the whole cluster.
+        CacheConfig::Redis(_) => Arc::new(CacheRateLimiter::new(cache)),
+    };
+    let rate_limiter: web::Data<dyn RateLimiter> = web::Data::from(rate_limiter);
     Log::info_title("Starting server");
     HttpServer::new(move || {
         App::new()
             .service(ws_handler)
             .service(pusher_event)
+            .service(pusher_batch_events)
+            .service(get_channels)
+            .service(get_channel)
+            .service(get_channel_users)
             .app_data(web::Data::new(local_adapter.clone()))
+            .app_data(app_manager.clone())
+            .app_data(config.clone())
+            .app_data(rate_limiter.clone())
     })
-        .bind(("127.0.0.1", 6001))?
-        .workers(32)
+        .bind((host, port))?
+        .workers(workers)
         .run()
         .await
 }
\ No newline at end of file
@@ -1,18 +1,11 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-// For fields that can be of any type, you might need a custom enum or use serde_json::Value
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(untagged)]
-pub enum AnyValue {
-    String(String),
-    Map(HashMap<String, serde_json::Value>),
-}
-
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct MessageData {
     pub channel_data: Option<String>,
     pub channel: Option<String>,
+    pub auth: Option<String>,
     pub user_data: Option<String>,
     #[serde(flatten)]
     pub extra: HashMap<String, serde_json::Value>, // For additional dynamic fields
@@ -23,7 +16,7 @@ pub struct PusherMessage {
     pub channel: Option<String>,
     pub name: Option<String>,
     pub event: String,
-    pub data: Option<MessageData>,
+    pub data: Option<serde_json::Value>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -36,22 +29,66 @@ pub(crate) struct PusherApiMessage {
     pub info: Option<PusherApiMessageInfo>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl PusherApiMessage {
+    /// The target channels, from either `channels` or the single `channel`.
+    pub fn channels(&self) -> Vec<String> {
+        match (&self.channels, &self.channel) {
+            (Some(channels), _) => channels.clone(),
+            (None, Some(channel)) => vec![channel.clone()],
+            (None, None) => vec![],
+        }
+    }
+
+    /// The frame subscribers of `channel` receive for this event.
+    pub fn frame(&self, channel: &str) -> serde_json::Value {
+        serde_json::json!({
+            "data": self.data,
+            "channel": channel,
+            "event": self.name,
+        })
+    }
+}
+
+/// The attributes requested through Pusher's comma separated `info` parameter,
+/// e.g. `"user_count,subscription_count"`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+#[serde(from = "String", into = "String")]
 pub struct PusherApiMessageInfo {
-    pub user_count: Option<u64>,
-    pub subscription_count: Option<u64>,
+    pub user_count: bool,
+    pub subscription_count: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct SentPusherMessage {
-    pub channel: Option<String>,
-    pub event: Option<String>,
-    pub data: Option<AnyValue>,
+impl PusherApiMessageInfo {
+    pub fn is_empty(&self) -> bool {
+        !self.user_count && !self.subscription_count
+    }
+}
+
+impl From<String> for PusherApiMessageInfo {
+    fn from(info: String) -> Self {
+        let attributes: Vec<&str> = info.split(',').map(str::trim).collect();
+        PusherApiMessageInfo {
+            user_count: attributes.contains(&"user_count"),
+            subscription_count: attributes.contains(&"subscription_count"),
+        }
+    }
 }
 
+impl From<PusherApiMessageInfo> for String {
+    fn from(info: PusherApiMessageInfo) -> Self {
+        let mut attributes = vec![];
+        if info.user_count {
+            attributes.push("user_count");
+        }
+        if info.subscription_count {
+            attributes.push("subscription_count");
+        }
+        attributes.join(",")
+    }
+}
+
+/// Body of `POST /apps/{app_id}/batch_events`.
 #[derive(Serialize, Deserialize, Debug)]
-#[serde(untagged)]
-pub enum WebSocketMessage {
-    ArrayBuffer(Vec<u8>),
-    PusherMessage(PusherMessage),
-}
\ No newline at end of file
+pub(crate) struct PusherBatchMessage {
+    pub batch: Vec<PusherApiMessage>,
+}
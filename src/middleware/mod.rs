@@ -0,0 +1,2 @@
+pub mod pusher_auth;
+pub mod rate_limit;
@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::StatusCode;
+use actix_web::web::{Bytes, Data, Query};
+use actix_web::{Error, HttpResponse};
+use futures_util::future::LocalBoxFuture;
+use crate::app_manager::AppManager;
+use crate::utils;
+
+/// How far `auth_timestamp` may drift from the server clock, in seconds.
+const MAX_TIMESTAMP_SKEW: i64 = 600;
+
+/// Verifies the Pusher REST API signature (`auth_key`, `auth_timestamp`,
+/// `auth_version`, `body_md5`, `auth_signature`) against the secret of the
+/// app in the `{app_id}` path segment.
+pub struct PusherAuth;
+
+impl<S, B> Transform<S, ServiceRequest> for PusherAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = PusherAuthMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(PusherAuthMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct PusherAuthMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for PusherAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        Box::pin(async move {
+            let body = req.extract::<Bytes>().await?;
+            let result = verify(&req, &body, chrono::Utc::now().timestamp());
+            req.set_payload(utils::bytes_to_payload(body));
+            match result {
+                Ok(()) => service.call(req).await.map(ServiceResponse::map_into_left_body),
+                Err(response) => Ok(req.into_response(response).map_into_right_body()),
+            }
+        })
+    }
+}
+
+fn unauthorized(message: &str) -> HttpResponse {
+    utils::error_response(StatusCode::UNAUTHORIZED, message)
+}
+
+/// Checks the request signature, with `now` as the server time in seconds.
+fn verify(req: &ServiceRequest, body: &Bytes, now: i64) -> Result<(), HttpResponse> {
+    let app_id = req.match_info().get("app_id").unwrap_or_default();
+    let app_manager = req.app_data::<Data<AppManager>>().expect("AppManager is registered as app data");
+    let app = app_manager.find_by_id(app_id).map_err(|e| utils::error_response(e.http_status(), &e.to_string()))?;
+
+    let params = Query::<BTreeMap<String, String>>::from_query(req.query_string())
+        .map_err(|_| unauthorized("Invalid query string"))?
+        .into_inner();
+    // Pusher signs the lower-cased parameter names, sorted, without the signature itself.
+    let mut params: BTreeMap<String, String> = params
+        .into_iter()
+        .map(|(key, value)| (key.to_lowercase(), value))
+        .collect();
+    let signature = params.remove("auth_signature").ok_or_else(|| unauthorized("Missing auth_signature"))?;
+
+    match params.get("auth_key") {
+        Some(key) if *key == app.key => {}
+        _ => return Err(unauthorized("Invalid auth_key")),
+    }
+
+    let timestamp: i64 = params
+        .get("auth_timestamp")
+        .and_then(|timestamp| timestamp.parse().ok())
+        .ok_or_else(|| unauthorized("Missing or invalid auth_timestamp"))?;
+    if (now - timestamp).abs() > MAX_TIMESTAMP_SKEW {
+        return Err(unauthorized("Timestamp expired"));
+    }
+
+    if params.get("auth_version").map(String::as_str) != Some("1.0") {
+        return Err(unauthorized("Unsupported auth_version"));
+    }
+
+    if !body.is_empty() && params.get("body_md5") != Some(&utils::md5_hex(body)) {
+        return Err(unauthorized("Invalid body_md5"));
+    }
+
+    let query = params
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join("&");
+    let string_to_sign = format!("{}\n{}\n{}", req.method(), req.path(), query);
+    let secret = app.secret.as_deref().ok_or_else(|| unauthorized("App has no secret configured"))?;
+    if !utils::verify_signature(secret, &string_to_sign, &signature) {
+        return Err(unauthorized("Invalid signature"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::MessageBody;
+    use actix_web::test::TestRequest;
+    use crate::app::AppConfig;
+
+    // The example request from Pusher's REST API documentation.
+    const KEY: &str = "278d425bdf160c739803";
+    const SECRET: &str = "7ad3773142a6692b25b8";
+    const TIMESTAMP: i64 = 1353088179;
+    const BODY: &str = r#"{"name":"foo","channels":["project-3"],"data":"{\"some\":\"data\"}"}"#;
+    const BODY_MD5: &str = "ec365a775a4cd0599faeb73354201b6f";
+    const SIGNATURE: &str = "da454824c97ba181a32ccc17a72625ba02771f50b50e1e7430e47a1f3f457e6c";
+
+    fn query() -> String {
+        format!(
+            "auth_key={}&auth_timestamp={}&auth_version=1.0&body_md5={}&auth_signature={}",
+            KEY, TIMESTAMP, BODY_MD5, SIGNATURE
+        )
+    }
+
+    /// Runs `verify` on `POST /apps/3/events?<query>`, returning the error message.
+    fn check(query: &str, body: &str, now: i64) -> Result<(), String> {
+        let app = AppConfig {
+            id: "3".to_string(),
+            key: KEY.to_string(),
+            secret: Some(SECRET.to_string()),
+            enabled: true,
+            ..Default::default()
+        };
+        let req = TestRequest::post()
+            .uri(&format!("/apps/3/events?{}", query))
+            .param("app_id", "3")
+            .app_data(Data::new(AppManager::new(vec![app])))
+            .to_srv_request();
+        verify(&req, &Bytes::from(body.to_string()), now).map_err(|response| {
+            let body = response.into_body().try_into_bytes().unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            body["error"].as_str().unwrap_or_default().to_string()
+        })
+    }
+
+    #[test]
+    fn accepts_the_documented_request() {
+        assert_eq!(check(&query(), BODY, TIMESTAMP), Ok(()));
+    }
+
+    #[test]
+    fn rejects_a_tampered_body() {
+        let body = BODY.replace("project-3", "project-4");
+        assert_eq!(check(&query(), &body, TIMESTAMP), Err("Invalid body_md5".to_string()));
+    }
+
+    #[test]
+    fn rejects_another_auth_key() {
+        let query = query().replace(KEY, "0000000000000000000");
+        assert_eq!(check(&query, BODY, TIMESTAMP), Err("Invalid auth_key".to_string()));
+    }
+
+    #[test]
+    fn rejects_a_skewed_timestamp() {
+        let skew = MAX_TIMESTAMP_SKEW + 1;
+        assert_eq!(check(&query(), BODY, TIMESTAMP + skew), Err("Timestamp expired".to_string()));
+        assert_eq!(check(&query(), BODY, TIMESTAMP - skew), Err("Timestamp expired".to_string()));
+        assert_eq!(check(&query(), BODY, TIMESTAMP + MAX_TIMESTAMP_SKEW), Ok(()));
+    }
+
+    #[test]
+    fn rejects_a_missing_or_wrong_signature() {
+        let unsigned = query().replace(&format!("&auth_signature={}", SIGNATURE), "");
+        assert_eq!(check(&unsigned, BODY, TIMESTAMP), Err("Missing auth_signature".to_string()));
+        let forged = query().replace(SIGNATURE, &"0".repeat(64));
+        assert_eq!(check(&forged, BODY, TIMESTAMP), Err("Invalid signature".to_string()));
+    }
+
+    #[test]
+    fn accepts_upper_case_parameter_names() {
+        let query = query().replace("auth_key", "AUTH_KEY").replace("body_md5", "Body_MD5").replace("auth_signature", "Auth_Signature");
+        assert_eq!(check(&query, BODY, TIMESTAMP), Ok(()));
+    }
+}
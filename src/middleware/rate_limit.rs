@@ -0,0 +1,165 @@
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use std::time::Duration;
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::web::{Bytes, Data};
+use actix_web::Error;
+use futures_util::future::LocalBoxFuture;
+use crate::app::AppConfig;
+use crate::app_manager::AppManager;
+use crate::message::PusherBatchMessage;
+use crate::rate_limiter::{RateLimitResult, RateLimiter};
+use crate::utils;
+
+/// Which of the app's HTTP API limits a route counts against.
+#[derive(Clone, Copy)]
+pub enum RateLimitKind {
+    /// `max_backend_events_per_second`
+    BackendEvents,
+    /// `max_read_requests_per_minute`
+    ReadRequests,
+}
+
+impl RateLimitKind {
+    fn key(&self) -> &'static str {
+        match self {
+            RateLimitKind::BackendEvents => "backend_events",
+            RateLimitKind::ReadRequests => "read_requests",
+        }
+    }
+
+    fn limit(&self, app: &AppConfig) -> Option<(u64, Duration)> {
+        match self {
+            RateLimitKind::BackendEvents => app.max_backend_events_per_second.map(|max| (max, Duration::from_secs(1))),
+            RateLimitKind::ReadRequests => app.max_read_requests_per_minute.map(|max| (max, Duration::from_secs(60))),
+        }
+    }
+}
+
+/// How many points a request takes from the limit.
+#[derive(Clone, Copy)]
+enum Cost {
+    One,
+    /// One per event in a `batch_events` body.
+    BatchEvents,
+}
+
+impl Cost {
+    async fn points(&self, req: &mut ServiceRequest) -> Result<u64, Error> {
+        match self {
+            Cost::One => Ok(1),
+            Cost::BatchEvents => {
+                let body = req.extract::<Bytes>().await?;
+                // A malformed body still costs a point; the handler rejects it.
+                let events = serde_json::from_slice::<PusherBatchMessage>(&body).map_or(1, |body| body.batch.len().max(1));
+                req.set_payload(utils::bytes_to_payload(body));
+                Ok(events as u64)
+            }
+        }
+    }
+}
+
+/// Applies the app's rate limit for the `{app_id}` in the path using the
+/// registered `RateLimiter`, answering 429 once it is exhausted.
+pub struct RateLimit {
+    kind: RateLimitKind,
+    cost: Cost,
+}
+
+impl RateLimit {
+    pub fn backend_events() -> Self {
+        RateLimit { kind: RateLimitKind::BackendEvents, cost: Cost::One }
+    }
+
+    /// Counts every event of the batch against the backend events limit.
+    pub fn batch_events() -> Self {
+        RateLimit { kind: RateLimitKind::BackendEvents, cost: Cost::BatchEvents }
+    }
+
+    pub fn read_requests() -> Self {
+        RateLimit { kind: RateLimitKind::ReadRequests, cost: Cost::One }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            kind: self.kind,
+            cost: self.cost,
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    kind: RateLimitKind,
+    cost: Cost,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let kind = self.kind;
+        let cost = self.cost;
+        Box::pin(async move {
+            let app_id = req.match_info().get("app_id").unwrap_or_default();
+            let app_manager = req.app_data::<Data<AppManager>>().expect("AppManager is registered as app data");
+            // Unknown apps and apps without a limit are left to the handler.
+            let limit = app_manager.find_by_id(app_id).ok().and_then(|app| kind.limit(&app));
+            let Some((max_points, window)) = limit else {
+                return service.call(req).await.map(ServiceResponse::map_into_left_body);
+            };
+
+            let rate_limiter = req
+                .app_data::<Data<dyn RateLimiter>>()
+                .expect("RateLimiter is registered as app data")
+                .clone();
+            let key = format!("{}:{}", app_id, kind.key());
+            let points = cost.points(&mut req).await?;
+            let result = rate_limiter.consume(&key, points, max_points, window).await;
+
+            if !result.allowed {
+                let mut response = utils::error_response(StatusCode::TOO_MANY_REQUESTS, "Too many requests");
+                insert_headers(response.headers_mut(), &result);
+                return Ok(req.into_response(response).map_into_right_body());
+            }
+            let mut response = service.call(req).await?;
+            insert_headers(response.headers_mut(), &result);
+            Ok(response.map_into_left_body())
+        })
+    }
+}
+
+fn insert_headers(headers: &mut HeaderMap, result: &RateLimitResult) {
+    for (name, value) in [
+        ("x-ratelimit-limit", result.limit),
+        ("x-ratelimit-remaining", result.remaining),
+        ("x-ratelimit-reset", result.reset_after),
+    ] {
+        headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+    }
+}
@@ -1,44 +1,150 @@
 use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
-use actix::{Actor, ActorFutureExt, Addr, AsyncContext, Context, Handler, Message, WrapFuture};
-use actix::dev::channel::AddressSender;
-use serde_json::json;
+use actix::{Actor, Handler, Message, MessageResult, Recipient};
+use bytestring::ByteString;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::app::AppConfig;
+use crate::channel_managers::presence_channel_manager::PresenceMember;
+use crate::channel_managers::ChannelType;
 use crate::log::Log;
-use crate::message::PusherApiMessage;
-use crate::WS;
-use crate::ws_message::{OnMessage, OnPusherMessage};
+use crate::message::{PusherApiMessage, PusherApiMessageInfo};
+use crate::pusher_error::PusherError;
+use crate::ws_message::OnPusherMessage;
+
+/// A user on a presence channel and the sockets it joined with.
+pub struct PresenceUser {
+    pub user_info: Value,
+    pub sockets: HashSet<String>,
+}
 
 pub struct Namespace {
     pub channels: HashMap<String, HashSet<String>>,
-    pub users: HashMap<String, HashSet<String>>,
-    pub app_id: String,
-    pub sockets: HashMap<String, Addr<WS>>,
+    /// Presence members per channel, keyed by `user_id`.
+    pub users: HashMap<String, HashMap<String, PresenceUser>>,
+    pub app: AppConfig,
+    pub sockets: HashMap<String, Recipient<OnPusherMessage>>,
+}
+
+/// What a socket leaving channels left behind on this node. Whether a member
+/// or channel is gone cluster-wide is for the adapter to decide.
+#[derive(Debug, Default)]
+pub struct Departures {
+    /// `(channel, user_id)` of presence users without sockets left here.
+    pub members_removed: Vec<(String, String)>,
+    pub channels_vacated: Vec<String>,
+}
+
+impl Namespace {
+    pub fn new(app: AppConfig) -> Self {
+        Namespace {
+            channels: HashMap::new(),
+            users: HashMap::new(),
+            app,
+            sockets: HashMap::new(),
+        }
+    }
+
+    /// Subscribes the socket and returns the channel's subscription count.
+    fn join_channel(&mut self, channel: &str, socket_id: String) -> usize {
+        let sockets = self.channels.entry(channel.to_string()).or_default();
+        sockets.insert(socket_id);
+        sockets.len()
+    }
+
+    fn channel_members(&self, channel: &str) -> ChannelMembers {
+        ChannelMembers {
+            subscription_count: self.channels.get(channel).map_or(0, HashSet::len),
+            user_ids: self.users.get(channel).map_or_else(HashSet::new, |members| members.keys().cloned().collect()),
+        }
+    }
+
+    /// Sends a message to every socket subscribed to `channel`, except `except`.
+    fn send_to_channel(&self, channel: &str, message: &Value, except: Option<&str>) {
+        let Some(socket_ids) = self.channels.get(channel) else {
+            return;
+        };
+        let message = ByteString::from(message.to_string());
+        for socket_id in socket_ids {
+            if except == Some(socket_id.as_str()) {
+                continue;
+            }
+            if let Some(socket_addr) = self.sockets.get(socket_id) {
+                socket_addr.do_send(OnPusherMessage {
+                    message: message.clone(),
+                });
+            }
+        }
+    }
+
+    /// Removes a socket from a channel, dropping the channel once it is empty.
+    fn remove_from_channel(&mut self, channel: &str, socket_id: &str, departures: &mut Departures) {
+        let Some(sockets) = self.channels.get_mut(channel) else {
+            return;
+        };
+        if !sockets.remove(socket_id) {
+            return;
+        }
+        let is_empty = sockets.is_empty();
+        if let Some(user_id) = self.leave_presence_channel(channel, socket_id) {
+            departures.members_removed.push((channel.to_string(), user_id));
+        }
+        if is_empty {
+            self.channels.remove(channel);
+            Log::websocket_title(format!("Channel {} vacated in app {}", channel, self.app.id).as_str());
+            departures.channels_vacated.push(channel.to_string());
+        }
+    }
+
+    /// Drops a socket from a presence roster, returning the user's id once it
+    /// has no sockets left on the channel.
+    fn leave_presence_channel(&mut self, channel: &str, socket_id: &str) -> Option<String> {
+        let members = self.users.get_mut(channel)?;
+        let (user_id, user) = members.iter_mut().find(|(_, user)| user.sockets.contains(socket_id))?;
+        user.sockets.remove(socket_id);
+        if !user.sockets.is_empty() {
+            return None;
+        }
+        let user_id = user_id.clone();
+        members.remove(&user_id);
+        if members.is_empty() {
+            self.users.remove(channel);
+        }
+        Some(user_id)
+    }
 }
 
 impl Actor for Namespace {
     type Context = actix::Context<Self>;
-    fn started(&mut self, ctx: &mut Self::Context) {
-        Log::websocket_title(format!("Namespace for app {}", self.app_id).as_str());
+    fn started(&mut self, _ctx: &mut Self::Context) {
+        Log::websocket_title(format!("Namespace for app {}", self.app.id).as_str());
     }
 
     fn stopped(&mut self, _ctx: &mut Self::Context) {
-        Log::websocket_title(format!("Namespace for app {} stopped", self.app_id).as_str());
+        Log::websocket_title(format!("Namespace for app {} stopped", self.app.id).as_str());
     }
 }
 
 #[derive(Message)]
-#[rtype(result = "()")]
+#[rtype(result = "Result<(), PusherError>")]
 pub struct AddSocket {
     pub(crate) socket_id: String,
-    pub(crate) socket_addr: Addr<WS>,
+    pub(crate) socket_addr: Recipient<OnPusherMessage>,
+    /// The app's connections on other nodes, counted against `max_connections`.
+    pub(crate) remote_connections: u64,
 }
 
 impl Handler<AddSocket> for Namespace {
-    type Result = ();
+    type Result = Result<(), PusherError>;
 
-    fn handle(&mut self, msg: AddSocket, _: &mut Self::Context) {
-        // Add the socket to the hashmap
-        self.sockets.insert(msg.socket_id.clone(), msg.socket_addr);
+    /// Registers a new connection, refusing it once the app reaches `max_connections`.
+    fn handle(&mut self, msg: AddSocket, _: &mut Self::Context) -> Self::Result {
+        if let Some(max_connections) = self.app.max_connections {
+            if self.sockets.len() as u64 + msg.remote_connections >= max_connections {
+                return Err(PusherError::OverConnectionQuota);
+            }
+        }
+        self.sockets.insert(msg.socket_id, msg.socket_addr);
+        Ok(())
     }
 }
 
@@ -53,44 +159,180 @@ impl Handler<AddToChannel> for Namespace {
     type Result = usize;
 
     fn handle(&mut self, msg: AddToChannel, _ctx: &mut Self::Context) -> Self::Result {
-        let socket_id = msg.socket_id.clone();
-        let channel = msg.channel.clone();
-        self.channels.entry(channel.clone()).or_default().insert(socket_id.clone());
-        self.channels.get(&channel).unwrap().len()
+        self.join_channel(&msg.channel, msg.socket_id)
+    }
+}
+
+/// This node's side of a presence subscription.
+pub struct PresenceJoin {
+    /// The local roster as `user_id => user_info`, the new member included.
+    pub roster: HashMap<String, Value>,
+    /// The user had no other socket on the channel here.
+    pub is_new_user: bool,
+    /// The socket is the channel's first subscriber here.
+    pub is_first_subscriber: bool,
+}
+
+#[derive(Message)]
+#[rtype(result = "PresenceJoin")]
+pub struct JoinPresenceChannel {
+    pub(crate) socket_id: String,
+    pub(crate) channel: String,
+    pub(crate) member: PresenceMember,
+}
+
+impl Handler<JoinPresenceChannel> for Namespace {
+    type Result = MessageResult<JoinPresenceChannel>;
+
+    /// Adds the socket to the channel and its user to the roster. A socket
+    /// that already joined keeps the user it joined as.
+    fn handle(&mut self, msg: JoinPresenceChannel, _ctx: &mut Self::Context) -> Self::Result {
+        if let Some(members) = self.users.get(&msg.channel) {
+            if members.values().any(|user| user.sockets.contains(&msg.socket_id)) {
+                return MessageResult(PresenceJoin {
+                    roster: roster(members),
+                    is_new_user: false,
+                    is_first_subscriber: false,
+                });
+            }
+        }
+        let PresenceMember { user_id, user_info } = msg.member;
+        let subscription_count = self.join_channel(&msg.channel, msg.socket_id.clone());
+        let members = self.users.entry(msg.channel).or_default();
+        let is_new_user = !members.contains_key(&user_id);
+        members
+            .entry(user_id)
+            .or_insert_with(|| PresenceUser {
+                user_info,
+                sockets: HashSet::new(),
+            })
+            .sockets
+            .insert(msg.socket_id);
+        MessageResult(PresenceJoin {
+            roster: roster(members),
+            is_new_user,
+            is_first_subscriber: subscription_count == 1,
+        })
+    }
+}
+
+fn roster(members: &HashMap<String, PresenceUser>) -> HashMap<String, Value> {
+    members
+        .iter()
+        .map(|(user_id, user)| (user_id.clone(), user.user_info.clone()))
+        .collect()
+}
+
+#[derive(Message)]
+#[rtype(result = "HashMap<String, Value>")]
+pub struct GetPresenceMembers {
+    pub channel: String,
+}
+
+impl Handler<GetPresenceMembers> for Namespace {
+    type Result = MessageResult<GetPresenceMembers>;
+
+    /// The local roster of a presence channel as `user_id => user_info`.
+    fn handle(&mut self, msg: GetPresenceMembers, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.users.get(&msg.channel).map_or_else(HashMap::new, roster))
     }
 }
 
 pub enum Channel {
     Ch(String),
-    Vec(Vec<String>),
 }
 
 #[derive(Message)]
-#[rtype(result = "usize")]
+#[rtype(result = "Departures")]
 pub struct RemoveFromChannel {
     pub socket_id: String,
     pub channel: Channel,
 }
 
 impl Handler<RemoveFromChannel> for Namespace {
-    type Result = usize;
+    type Result = MessageResult<RemoveFromChannel>;
 
     fn handle(&mut self, msg: RemoveFromChannel, _ctx: &mut Self::Context) -> Self::Result {
-        match msg.channel {
-            Channel::Ch(channel) => {
-                self.channels.entry(channel.clone()).or_default().remove(&msg.socket_id);
-                self.channels.get(&channel).unwrap().len()
-            }
-            Channel::Vec(channels) => {
-                for channel in channels {
-                    self.channels.entry(channel.clone()).or_default().remove(&msg.socket_id);
-                }
-                self.channels.values().map(|x| x.len()).sum()
-            }
+        let mut departures = Departures::default();
+        let Channel::Ch(channel) = msg.channel;
+        self.remove_from_channel(&channel, &msg.socket_id, &mut departures);
+        MessageResult(departures)
+    }
+}
+
+/// Live attributes of a channel, as reported by the HTTP API.
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct ChannelInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub occupied: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_count: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscription_count: Option<usize>,
+}
+
+/// This node's share of a channel's membership; shares from several nodes
+/// are merged into the cluster-wide view.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ChannelMembers {
+    pub subscription_count: usize,
+    /// Distinct presence `user_id`s, however many sockets each holds.
+    pub user_ids: HashSet<String>,
+}
+
+impl ChannelMembers {
+    pub fn merge(&mut self, other: ChannelMembers) {
+        self.subscription_count += other.subscription_count;
+        self.user_ids.extend(other.user_ids);
+    }
+
+    /// The requested attributes of `channel`; only presence channels have a `user_count`.
+    pub fn info(&self, channel: &str, info: PusherApiMessageInfo) -> ChannelInfo {
+        let is_presence = ChannelType::from_name(channel) == ChannelType::Presence;
+        ChannelInfo {
+            occupied: None,
+            user_count: (info.user_count && is_presence).then_some(self.user_ids.len()),
+            subscription_count: info.subscription_count.then_some(self.subscription_count),
         }
     }
 }
 
+#[derive(Message)]
+#[rtype(result = "ChannelMembers")]
+pub struct GetChannelMembers {
+    pub channel: String,
+}
+
+impl Handler<GetChannelMembers> for Namespace {
+    type Result = MessageResult<GetChannelMembers>;
+
+    fn handle(&mut self, msg: GetChannelMembers, _ctx: &mut Self::Context) -> Self::Result {
+        MessageResult(self.channel_members(&msg.channel))
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "HashMap<String, ChannelMembers>")]
+pub struct GetOccupiedChannels {
+    pub prefix: Option<String>,
+}
+
+impl Handler<GetOccupiedChannels> for Namespace {
+    type Result = MessageResult<GetOccupiedChannels>;
+
+    /// Occupied channels, optionally only those starting with `prefix`.
+    fn handle(&mut self, msg: GetOccupiedChannels, _ctx: &mut Self::Context) -> Self::Result {
+        let prefix = msg.prefix.unwrap_or_default();
+        MessageResult(
+            self.channels
+                .keys()
+                .filter(|channel| channel.starts_with(&prefix))
+                .map(|channel| (channel.clone(), self.channel_members(channel)))
+                .collect(),
+        )
+    }
+}
+
 #[derive(Message)]
 #[rtype(result = "Vec<String>")]
 pub struct GetSockets;
@@ -110,35 +352,170 @@ pub struct BroadcastMessage(pub PusherApiMessage); // Message to be broadcasted
 impl Handler<BroadcastMessage> for Namespace {
     type Result = ();
 
+    /// Delivers the event to the subscribers of its channel, skipping the
+    /// socket that triggered it when `socket_id` is set.
     fn handle(&mut self, msg: BroadcastMessage, _: &mut Self::Context) {
-        let message = json!({
-            "data": msg.0.data,
-            "channel": msg.0.channel,
-            "event": msg.0.name,
-        });
-        for socket_addr in self.sockets.values() {
-            socket_addr.do_send(OnPusherMessage {
-                message: message.clone(),
-            });
-        }
+        let Some(channel) = msg.0.channel.as_deref() else {
+            return;
+        };
+        let message = msg.0.frame(channel);
+        self.send_to_channel(channel, &message, msg.0.socket_id.as_deref());
     }
 }
 
 #[derive(Message)]
-#[rtype(result = "usize")]
+#[rtype(result = "()")]
+pub struct SendToChannel {
+    pub(crate) channel: String,
+    pub(crate) message: Value,
+    pub(crate) except: Option<String>,
+}
+
+impl Handler<SendToChannel> for Namespace {
+    type Result = ();
+
+    fn handle(&mut self, msg: SendToChannel, _: &mut Self::Context) {
+        self.send_to_channel(&msg.channel, &msg.message, msg.except.as_deref());
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "Departures")]
 pub struct RemoveSocket {
     pub(crate) socket_id: String,
-    pub socket_addr: Addr<WS>,
 }
 
 impl Handler<RemoveSocket> for Namespace {
-    type Result = usize;
+    type Result = MessageResult<RemoveSocket>;
 
     fn handle(&mut self, msg: RemoveSocket, _: &mut Self::Context) -> Self::Result {
         self.sockets.remove(&msg.socket_id);
-        self.channels.values_mut().for_each(|x| {
-            x.remove(&msg.socket_id);
+        let channels: Vec<String> = self.channels
+            .iter()
+            .filter(|(_, sockets)| sockets.contains(&msg.socket_id))
+            .map(|(channel, _)| channel.clone())
+            .collect();
+        let mut departures = Departures::default();
+        for channel in channels {
+            self.remove_from_channel(&channel, &msg.socket_id, &mut departures);
+        }
+        MessageResult(departures)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use actix::Context;
+    use serde_json::json;
+
+    /// A subscriber that keeps the frames exactly as it received them.
+    struct Sink {
+        frames: Arc<Mutex<Vec<ByteString>>>,
+    }
+
+    impl Actor for Sink {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<OnPusherMessage> for Sink {
+        type Result = ();
+
+        fn handle(&mut self, msg: OnPusherMessage, _: &mut Self::Context) {
+            self.frames.lock().unwrap().push(msg.message);
+        }
+    }
+
+    #[actix_web::test]
+    async fn fan_out_serializes_each_event_once() {
+        let frames: Arc<Mutex<Vec<ByteString>>> = Arc::default();
+        let mut namespace = Namespace::new(AppConfig::default());
+        for i in 0..1000 {
+            let socket_id = format!("{}.1", i);
+            let sink = Sink { frames: frames.clone() }.start();
+            namespace.sockets.insert(socket_id.clone(), sink.recipient());
+            namespace.join_channel("news", socket_id);
+        }
+
+        let event = json!({"event": "update", "channel": "news", "data": "{}"});
+        namespace.send_to_channel("news", &event, Some("0.1"));
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while frames.lock().unwrap().len() < 999 && Instant::now() < deadline {
+            actix_web::rt::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 999);
+        assert_eq!(frames[0], event.to_string());
+        // Every subscriber holds the same buffer, not a copy of it.
+        let buffer = frames[0].as_ptr();
+        assert!(frames.iter().all(|frame| frame.as_ptr() == buffer));
+    }
+}
+
+#[cfg(all(test, sockudo_bench))]
+mod benches {
+    extern crate test;
+
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use actix::Context;
+    use serde_json::json;
+
+    /// A subscriber that only counts the frames it receives.
+    struct Counter {
+        delivered: Arc<AtomicUsize>,
+    }
+
+    impl Actor for Counter {
+        type Context = Context<Self>;
+    }
+
+    impl Handler<OnPusherMessage> for Counter {
+        type Result = ();
+
+        fn handle(&mut self, _: OnPusherMessage, _: &mut Self::Context) {
+            self.delivered.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Publishes one event to a channel of `subscribers` sockets per iteration,
+    /// until every socket received it.
+    fn fan_out(b: &mut test::Bencher, subscribers: usize) {
+        let system = actix::System::new();
+        let delivered = Arc::new(AtomicUsize::new(0));
+        let namespace = system.block_on(async {
+            let mut namespace = Namespace::new(AppConfig::default());
+            for i in 0..subscribers {
+                let socket_id = format!("{}.1", i);
+                let counter = Counter { delivered: delivered.clone() }.start();
+                namespace.sockets.insert(socket_id.clone(), counter.recipient());
+                namespace.join_channel("news", socket_id);
+            }
+            namespace
+        });
+        let event = json!({"event": "update", "channel": "news", "data": "x".repeat(1024)});
+        b.iter(|| {
+            let target = delivered.load(Ordering::Relaxed) + subscribers;
+            namespace.send_to_channel("news", &event, None);
+            system.block_on(async {
+                while delivered.load(Ordering::Relaxed) < target {
+                    tokio::task::yield_now().await;
+                }
+            });
         });
-        self.sockets.len()
+    }
+
+    #[bench]
+    fn fan_out_to_1k_subscribers(b: &mut test::Bencher) {
+        fan_out(b, 1_000);
+    }
+
+    #[bench]
+    fn fan_out_to_10k_subscribers(b: &mut test::Bencher) {
+        fan_out(b, 10_000);
     }
 }
@@ -0,0 +1,79 @@
+use std::fmt;
+use actix_web_actors::ws;
+use serde_json::{json, Value};
+use crate::app_manager::AppError;
+
+/// Errors reported to WebSocket clients as `pusher:error` frames.
+///
+/// The code ranges follow the Pusher protocol: 4000-4099 close the connection
+/// for good, 4100-4199 ask the client to reconnect after a backoff, 4200-4299
+/// to reconnect immediately, and 4300-4399 leave the connection open.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PusherError {
+    App(AppError),
+    OverConnectionQuota,
+    InvalidVersionString(String),
+    UnsupportedProtocolVersion(String),
+    OverCapacity,
+    PongNotReceived,
+    InvalidMessage(String),
+    ClientEventRejected(String),
+}
+
+impl PusherError {
+    pub fn code(&self) -> u16 {
+        match self {
+            PusherError::App(error) => error.ws_code(),
+            PusherError::OverConnectionQuota => 4004,
+            PusherError::InvalidVersionString(_) => 4006,
+            PusherError::UnsupportedProtocolVersion(_) => 4007,
+            PusherError::OverCapacity => 4100,
+            PusherError::PongNotReceived => 4201,
+            PusherError::InvalidMessage(_) => 4300,
+            PusherError::ClientEventRejected(_) => 4301,
+        }
+    }
+
+    /// Errors below 4300 end the connection after the error frame.
+    pub fn closes_connection(&self) -> bool {
+        self.code() < 4300
+    }
+
+    pub fn to_frame(&self) -> Value {
+        json!({
+            "event": "pusher:error",
+            "data": {
+                "code": self.code(),
+                "message": self.to_string(),
+            },
+        })
+    }
+
+    pub fn close_reason(&self) -> ws::CloseReason {
+        ws::CloseReason {
+            code: ws::CloseCode::Other(self.code()),
+            description: Some(self.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for PusherError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PusherError::App(error) => write!(f, "{}", error),
+            PusherError::OverConnectionQuota => write!(f, "Over connection quota"),
+            PusherError::InvalidVersionString(version) => write!(f, "Invalid version string: {}", version),
+            PusherError::UnsupportedProtocolVersion(version) => write!(f, "Unsupported protocol version: {}", version),
+            PusherError::OverCapacity => write!(f, "Over capacity"),
+            PusherError::PongNotReceived => write!(f, "Pong reply not received"),
+            PusherError::InvalidMessage(reason) => write!(f, "Invalid message: {}", reason),
+            PusherError::ClientEventRejected(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl From<AppError> for PusherError {
+    fn from(error: AppError) -> Self {
+        PusherError::App(error)
+    }
+}
@@ -0,0 +1,37 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use futures_util::future::LocalBoxFuture;
+use crate::cache::CacheManager;
+use crate::rate_limiter::{RateLimitResult, RateLimiter};
+
+/// Fixed window counters kept in a `CacheManager`, so every node sharing the
+/// cache enforces the same limits.
+pub struct CacheRateLimiter {
+    cache: Arc<dyn CacheManager>,
+}
+
+impl CacheRateLimiter {
+    pub fn new(cache: Arc<dyn CacheManager>) -> Self {
+        CacheRateLimiter { cache }
+    }
+}
+
+impl RateLimiter for CacheRateLimiter {
+    fn consume(&self, key: &str, points: u64, max_points: u64, window: Duration) -> LocalBoxFuture<'_, RateLimitResult> {
+        // Windows are aligned to the epoch so all nodes agree on the current one.
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        let window_ms = (window.as_millis() as u64).max(1);
+        let window_index = now / window_ms;
+        let reset_after = ((window_index + 1) * window_ms - now).div_ceil(1000);
+        let key = format!("rate_limit:{}:{}", key, window_index);
+        Box::pin(async move {
+            let used = self.cache.increment(&key, points, window).await;
+            RateLimitResult {
+                allowed: used <= max_points,
+                limit: max_points,
+                remaining: max_points.saturating_sub(used),
+                reset_after,
+            }
+        })
+    }
+}
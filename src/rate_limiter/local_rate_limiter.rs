@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use futures_util::future::{ready, LocalBoxFuture};
+use crate::rate_limiter::{RateLimitResult, RateLimiter};
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Token bucket rate limiter kept in this process' memory.
+#[derive(Default)]
+pub struct LocalRateLimiter {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl LocalRateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl RateLimiter for LocalRateLimiter {
+    fn consume(&self, key: &str, points: u64, max_points: u64, window: Duration) -> LocalBoxFuture<'_, RateLimitResult> {
+        let now = Instant::now();
+        let capacity = max_points as f64;
+        let refill_per_second = capacity / window.as_secs_f64();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated_at: now,
+        });
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * refill_per_second).min(capacity);
+        bucket.updated_at = now;
+
+        let allowed = bucket.tokens >= points as f64;
+        if allowed {
+            bucket.tokens -= points as f64;
+        }
+        let reset_after = ((capacity - bucket.tokens) / refill_per_second).ceil() as u64;
+        Box::pin(ready(RateLimitResult {
+            allowed,
+            limit: max_points,
+            remaining: bucket.tokens.floor() as u64,
+            reset_after,
+        }))
+    }
+}
@@ -0,0 +1,22 @@
+pub mod cache_rate_limiter;
+pub mod local_rate_limiter;
+
+use std::time::Duration;
+use futures_util::future::LocalBoxFuture;
+
+/// Outcome of consuming points from a rate limit bucket.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitResult {
+    pub allowed: bool,
+    pub limit: u64,
+    pub remaining: u64,
+    /// Seconds until the bucket is full again.
+    pub reset_after: u64,
+}
+
+/// Backend for the HTTP API rate limits. The in-memory limiter only sees its
+/// own node, so multi-node setups can plug in a shared store instead.
+pub trait RateLimiter: Send + Sync {
+    /// Takes `points` from the bucket `key`, which refills `max_points` per `window`.
+    fn consume(&self, key: &str, points: u64, max_points: u64, window: Duration) -> LocalBoxFuture<'_, RateLimitResult>;
+}
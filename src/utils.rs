@@ -1,4 +1,11 @@
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use actix_web::HttpResponse;
+use hmac::{Hmac, Mac};
 use rand::Rng;
+use serde_json::json;
+use sha2::Sha256;
+use crate::log::Log;
 
 pub(crate) fn generate_socket_id() -> String {
     let mut rng = rand::thread_rng(); // Get a random number generator
@@ -12,4 +19,48 @@ pub(crate) fn generate_socket_id() -> String {
 
     // Format the random numbers into a String with a dot separator
     format!("{}.{}", random_number(min, max), random_number(min, max))
-}
\ No newline at end of file
+}
+
+fn hmac_sha256(secret: &str, data: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(data.as_bytes());
+    mac
+}
+
+/// A random id for this server in the cluster, or for a cluster request.
+pub(crate) fn generate_node_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+/// Hex encoded HMAC-SHA256 of `data`, as used by Pusher signatures.
+pub(crate) fn sign(secret: &str, data: &str) -> String {
+    hex::encode(hmac_sha256(secret, data).finalize().into_bytes())
+}
+
+/// Checks a hex encoded HMAC-SHA256 signature in constant time.
+pub(crate) fn verify_signature(secret: &str, data: &str, signature: &str) -> bool {
+    match hex::decode(signature) {
+        Ok(signature) => hmac_sha256(secret, data).verify_slice(&signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Turns a body read by a middleware back into a payload for the next service.
+pub(crate) fn bytes_to_payload(body: Bytes) -> actix_web::dev::Payload {
+    let (_, mut payload) = actix_http::h1::Payload::create(true);
+    payload.unread_data(body);
+    payload.into()
+}
+
+pub(crate) fn md5_hex(data: &[u8]) -> String {
+    format!("{:x}", md5::compute(data))
+}
+
+/// A Pusher style `{"error": ..., "code": ...}` API error response.
+pub(crate) fn error_response(status: StatusCode, message: &str) -> HttpResponse {
+    Log::warning_title(format!("Rejected API request: {}", message).as_str());
+    HttpResponse::build(status).json(json!({
+        "error": message,
+        "code": status.as_u16(),
+    }))
+}
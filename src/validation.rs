@@ -0,0 +1,301 @@
+use std::fmt;
+use actix_web::http::StatusCode;
+use serde_json::Value;
+use crate::app::AppConfig;
+use crate::channel_managers::ChannelType;
+use crate::message::PusherApiMessage;
+
+/// A request that breaks one of the app's limits or Pusher's naming rules.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    MissingEventName,
+    MissingChannels,
+    InvalidChannelName(String),
+    ChannelNameTooLong(u64),
+    EventNameTooLong(u64),
+    PayloadTooLarge(u64),
+    TooManyChannels(u64),
+    BatchTooLarge(u64),
+    UserCountRequiresPresence,
+    NotPresenceChannel(String),
+    InvalidSocketId(String),
+    EncryptedChannelNotAlone,
+    UnencryptedPayload(String),
+}
+
+impl ValidationError {
+    pub fn http_status(&self) -> StatusCode {
+        match self {
+            ValidationError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::MissingEventName => write!(f, "The event name is required"),
+            ValidationError::MissingChannels => write!(f, "At least one channel is required"),
+            ValidationError::InvalidChannelName(channel) => write!(f, "The channel name {} contains invalid characters", channel),
+            ValidationError::ChannelNameTooLong(limit) => write!(f, "The channel name is longer than the allowed {} characters", limit),
+            ValidationError::EventNameTooLong(limit) => write!(f, "The event name is longer than the allowed {} characters", limit),
+            ValidationError::PayloadTooLarge(limit) => write!(f, "The event data should be less than {} KB", limit),
+            ValidationError::TooManyChannels(limit) => write!(f, "Cannot broadcast to more than {} channels at once", limit),
+            ValidationError::BatchTooLarge(limit) => write!(f, "Cannot send more than {} events in a batch", limit),
+            ValidationError::UserCountRequiresPresence => write!(f, "user_count may only be requested for presence channels"),
+            ValidationError::NotPresenceChannel(channel) => write!(f, "{} is not a presence channel", channel),
+            ValidationError::InvalidSocketId(socket_id) => write!(f, "Invalid socket id {}", socket_id),
+            ValidationError::EncryptedChannelNotAlone => write!(f, "Events to an encrypted channel cannot be sent to other channels at once"),
+            ValidationError::UnencryptedPayload(channel) => write!(f, "Events to {} must carry an encrypted payload with a nonce and ciphertext", channel),
+        }
+    }
+}
+
+/// Channel names may only use `A-Z a-z 0-9 _ - = @ , . ;` and must fit the app's length limit.
+pub fn validate_channel_name(app: &AppConfig, channel: &str) -> Result<(), ValidationError> {
+    if channel.len() as u64 > app.channel_name_limit() {
+        return Err(ValidationError::ChannelNameTooLong(app.channel_name_limit()));
+    }
+    let valid_char = |c: char| c.is_ascii_alphanumeric() || "_-=@,.;".contains(c);
+    if channel.is_empty() || !channel.chars().all(valid_char) {
+        return Err(ValidationError::InvalidChannelName(channel.to_string()));
+    }
+    Ok(())
+}
+
+pub fn validate_event_name(app: &AppConfig, name: &str) -> Result<(), ValidationError> {
+    if name.len() as u64 > app.event_name_limit() {
+        return Err(ValidationError::EventNameTooLong(app.event_name_limit()));
+    }
+    Ok(())
+}
+
+pub fn validate_payload_size(app: &AppConfig, size_in_bytes: usize) -> Result<(), ValidationError> {
+    if size_in_bytes as u64 > app.event_payload_limit_in_kb() * 1024 {
+        return Err(ValidationError::PayloadTooLarge(app.event_payload_limit_in_kb()));
+    }
+    Ok(())
+}
+
+/// Validates an event published through the HTTP API.
+pub fn validate_api_message(app: &AppConfig, message: &PusherApiMessage) -> Result<(), ValidationError> {
+    let name = message.name.as_deref().ok_or(ValidationError::MissingEventName)?;
+    validate_event_name(app, name)?;
+    let channels = message.channels();
+    if channels.is_empty() {
+        return Err(ValidationError::MissingChannels);
+    }
+    if channels.len() as u64 > app.event_channels_limit() {
+        return Err(ValidationError::TooManyChannels(app.event_channels_limit()));
+    }
+    for channel in &channels {
+        validate_channel_name(app, channel)?;
+    }
+    if let Some(socket_id) = &message.socket_id {
+        validate_socket_id(socket_id)?;
+    }
+    if let Some(channel) = channels.iter().find(|channel| ChannelType::from_name(channel) == ChannelType::PrivateEncrypted) {
+        // Each encrypted channel has its own key, so one payload cannot serve several channels.
+        if channels.len() > 1 {
+            return Err(ValidationError::EncryptedChannelNotAlone);
+        }
+        validate_encrypted_payload(channel, message.data.as_deref())?;
+    }
+    validate_payload_size(app, message.data.as_deref().map_or(0, str::len))
+}
+
+/// Socket ids look like `123.456`, as handed out on `pusher:connection_established`.
+pub fn validate_socket_id(socket_id: &str) -> Result<(), ValidationError> {
+    let is_number = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+    match socket_id.split_once('.') {
+        Some((left, right)) if is_number(left) && is_number(right) => Ok(()),
+        _ => Err(ValidationError::InvalidSocketId(socket_id.to_string())),
+    }
+}
+
+/// Encrypted channels only relay the JSON encoded `{"nonce", "ciphertext"}`
+/// produced by the server libraries, never plaintext.
+fn validate_encrypted_payload(channel: &str, data: Option<&str>) -> Result<(), ValidationError> {
+    let payload: Option<Value> = data.and_then(|data| serde_json::from_str(data).ok());
+    let has_field = |field: &str| payload
+        .as_ref()
+        .and_then(|payload| payload[field].as_str())
+        .is_some_and(|value| !value.is_empty());
+    if has_field("nonce") && has_field("ciphertext") {
+        Ok(())
+    } else {
+        Err(ValidationError::UnencryptedPayload(channel.to_string()))
+    }
+}
+
+/// Checks the batch size against `max_event_batch_size`, then every event on its own.
+pub fn validate_batch(app: &AppConfig, batch: &[PusherApiMessage]) -> Result<(), ValidationError> {
+    if batch.len() as u64 > app.event_batch_limit() {
+        return Err(ValidationError::BatchTooLarge(app.event_batch_limit()));
+    }
+    batch.iter().try_for_each(|message| {
+        validate_api_message(app, message)?;
+        validate_info(message)
+    })
+}
+
+/// `user_count` only exists on presence channels, so asking for it elsewhere is an error.
+pub fn validate_info(message: &PusherApiMessage) -> Result<(), ValidationError> {
+    let user_count = message.info.is_some_and(|info| info.user_count);
+    let all_presence = message.channels().iter().all(|channel| ChannelType::from_name(channel) == ChannelType::Presence);
+    if user_count && !all_presence {
+        return Err(ValidationError::UserCountRequiresPresence);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::PusherApiMessageInfo;
+
+    fn message(name: Option<&str>, channels: &[&str], data: &str) -> PusherApiMessage {
+        PusherApiMessage {
+            name: name.map(str::to_string),
+            data: Some(data.to_string()),
+            channel: None,
+            channels: Some(channels.iter().map(|channel| channel.to_string()).collect()),
+            socket_id: None,
+            info: None,
+        }
+    }
+
+    #[test]
+    fn validates_channel_names() {
+        let app = AppConfig::default();
+        let cases = [
+            ("news", Ok(())),
+            ("private-chat_1=a@b,c.d;e", Ok(())),
+            ("presence-room", Ok(())),
+            (&*"a".repeat(200), Ok(())),
+            (&*"a".repeat(201), Err(ValidationError::ChannelNameTooLong(200))),
+            ("", Err(ValidationError::InvalidChannelName(String::new()))),
+            ("news room", Err(ValidationError::InvalidChannelName("news room".to_string()))),
+            ("news/room", Err(ValidationError::InvalidChannelName("news/room".to_string()))),
+            ("nëws", Err(ValidationError::InvalidChannelName("nëws".to_string()))),
+            ("news\n", Err(ValidationError::InvalidChannelName("news\n".to_string()))),
+        ];
+        for (channel, expected) in cases {
+            assert_eq!(validate_channel_name(&app, channel), expected, "{:?}", channel);
+        }
+
+        let app = AppConfig {
+            max_channel_name_length: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(validate_channel_name(&app, "news"), Ok(()));
+        assert_eq!(validate_channel_name(&app, "newss"), Err(ValidationError::ChannelNameTooLong(4)));
+    }
+
+    #[test]
+    fn validates_event_names_and_payload_sizes() {
+        let app = AppConfig::default();
+        assert_eq!(validate_event_name(&app, &"e".repeat(200)), Ok(()));
+        assert_eq!(validate_event_name(&app, &"e".repeat(201)), Err(ValidationError::EventNameTooLong(200)));
+        assert_eq!(validate_payload_size(&app, 10 * 1024), Ok(()));
+        assert_eq!(validate_payload_size(&app, 10 * 1024 + 1), Err(ValidationError::PayloadTooLarge(10)));
+
+        let app = AppConfig {
+            max_event_name_length: Some(3),
+            max_event_payload_in_kb: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(validate_event_name(&app, "four"), Err(ValidationError::EventNameTooLong(3)));
+        assert_eq!(validate_payload_size(&app, 1025), Err(ValidationError::PayloadTooLarge(1)));
+    }
+
+    #[test]
+    fn validates_socket_ids() {
+        for socket_id in ["1.2", "123456.7890"] {
+            assert_eq!(validate_socket_id(socket_id), Ok(()));
+        }
+        for socket_id in ["", "1", "1.", ".2", "1.2.3", "a.2", "1.b", "-1.2", " 1.2"] {
+            assert_eq!(
+                validate_socket_id(socket_id),
+                Err(ValidationError::InvalidSocketId(socket_id.to_string())),
+                "{:?}",
+                socket_id
+            );
+        }
+    }
+
+    #[test]
+    fn validates_api_messages() {
+        let app = AppConfig::default();
+        let encrypted = r#"{"nonce":"bm9uY2U=","ciphertext":"Y2lwaGVy"}"#;
+        let too_many_channels: Vec<String> = (0..101).map(|i| format!("channel-{}", i)).collect();
+        let too_many_channels: Vec<&str> = too_many_channels.iter().map(String::as_str).collect();
+        let with_socket_id = |socket_id: &str| PusherApiMessage {
+            socket_id: Some(socket_id.to_string()),
+            ..message(Some("update"), &["news"], "{}")
+        };
+        let cases = [
+            (message(Some("update"), &["news"], "{}"), Ok(())),
+            (message(Some("update"), &["news", "presence-room"], "{}"), Ok(())),
+            (message(None, &["news"], "{}"), Err(ValidationError::MissingEventName)),
+            (message(Some("update"), &[], "{}"), Err(ValidationError::MissingChannels)),
+            (message(Some("update"), &too_many_channels, "{}"), Err(ValidationError::TooManyChannels(100))),
+            (message(Some("update"), &["news", "bad name"], "{}"), Err(ValidationError::InvalidChannelName("bad name".to_string()))),
+            (message(Some("update"), &["news"], &"x".repeat(10 * 1024 + 1)), Err(ValidationError::PayloadTooLarge(10))),
+            (with_socket_id("1.2"), Ok(())),
+            (with_socket_id("nope"), Err(ValidationError::InvalidSocketId("nope".to_string()))),
+            (message(Some("update"), &["private-encrypted-chat"], encrypted), Ok(())),
+            (message(Some("update"), &["private-encrypted-chat", "news"], encrypted), Err(ValidationError::EncryptedChannelNotAlone)),
+            (
+                message(Some("update"), &["private-encrypted-chat"], "plain"),
+                Err(ValidationError::UnencryptedPayload("private-encrypted-chat".to_string())),
+            ),
+            (
+                message(Some("update"), &["private-encrypted-chat"], r#"{"nonce":"","ciphertext":"Y2lwaGVy"}"#),
+                Err(ValidationError::UnencryptedPayload("private-encrypted-chat".to_string())),
+            ),
+        ];
+        for (message, expected) in cases {
+            assert_eq!(validate_api_message(&app, &message), expected, "{:?}", message.channels());
+        }
+    }
+
+    #[test]
+    fn validates_info_attributes() {
+        let user_count = Some(PusherApiMessageInfo::from("user_count".to_string()));
+        let subscription_count = Some(PusherApiMessageInfo::from("subscription_count".to_string()));
+        let cases = [
+            (&["presence-room"][..], user_count, Ok(())),
+            (&["presence-a", "presence-b"][..], user_count, Ok(())),
+            (&["news"][..], user_count, Err(ValidationError::UserCountRequiresPresence)),
+            (&["presence-room", "news"][..], user_count, Err(ValidationError::UserCountRequiresPresence)),
+            (&["news"][..], subscription_count, Ok(())),
+            (&["news"][..], None, Ok(())),
+        ];
+        for (channels, info, expected) in cases {
+            let message = PusherApiMessage {
+                info,
+                ..message(Some("update"), channels, "{}")
+            };
+            assert_eq!(validate_info(&message), expected, "{:?}", channels);
+        }
+    }
+
+    #[test]
+    fn validates_batches() {
+        let app = AppConfig::default();
+        let event = message(Some("update"), &["news"], "{}");
+        assert_eq!(validate_batch(&app, &vec![event.clone(); 10]), Ok(()));
+        assert_eq!(validate_batch(&app, &vec![event.clone(); 11]), Err(ValidationError::BatchTooLarge(10)));
+        assert_eq!(validate_batch(&app, &[]), Ok(()));
+
+        let unnamed = message(None, &["news"], "{}");
+        assert_eq!(validate_batch(&app, &[event.clone(), unnamed]), Err(ValidationError::MissingEventName));
+        let user_count = PusherApiMessage {
+            info: Some(PusherApiMessageInfo::from("user_count".to_string())),
+            ..event
+        };
+        assert_eq!(validate_batch(&app, &[user_count]), Err(ValidationError::UserCountRequiresPresence));
+    }
+}
@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use actix::{Actor, AsyncContext, Context, Handler, Message};
+use serde_json::json;
+use crate::app::AppConfig;
+use crate::app_manager::AppManager;
+use crate::log::Log;
+use crate::utils;
+use crate::webhook::WebhookEvent;
+
+/// How long events are collected before they are delivered together.
+const BATCH_INTERVAL: Duration = Duration::from_secs(1);
+/// An app's batch is delivered early once it holds this many events.
+const MAX_BATCH_SIZE: usize = 50;
+const MAX_ATTEMPTS: u32 = 3;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Batches webhook events per app and POSTs them, signed, to every webhook
+/// of the app whose filters accept them.
+pub struct WebhookSender {
+    app_manager: Arc<AppManager>,
+    client: reqwest::Client,
+    pending: HashMap<String, Vec<WebhookEvent>>,
+}
+
+impl WebhookSender {
+    pub fn new(app_manager: Arc<AppManager>) -> Self {
+        WebhookSender {
+            app_manager,
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .expect("the webhook HTTP client can be built"),
+            pending: HashMap::new(),
+        }
+    }
+
+    fn flush(&mut self) {
+        let app_ids: Vec<String> = self.pending.keys().cloned().collect();
+        for app_id in app_ids {
+            self.flush_app(&app_id);
+        }
+    }
+
+    fn flush_app(&mut self, app_id: &str) {
+        let Some(events) = self.pending.remove(app_id) else {
+            return;
+        };
+        let Ok(app) = self.app_manager.find_by_id(app_id) else {
+            return;
+        };
+        let Some(secret) = app.secret.as_deref() else {
+            Log::warning_title(format!("App {} has no secret to sign webhooks with", app.id).as_str());
+            return;
+        };
+        let time_ms = chrono::Utc::now().timestamp_millis();
+        for webhook in &app.webhooks {
+            let events: Vec<&WebhookEvent> = events.iter().filter(|event| webhook.accepts(event)).collect();
+            if events.is_empty() {
+                continue;
+            }
+            let body = json!({
+                "time_ms": time_ms,
+                "events": events,
+            }).to_string();
+            let request = self.client
+                .post(&webhook.url)
+                .header("Content-Type", "application/json")
+                .header("X-Pusher-Key", &app.key)
+                .header("X-Pusher-Signature", utils::sign(secret, &body))
+                .body(body);
+            actix::spawn(deliver(request, webhook.url.clone()));
+        }
+    }
+}
+
+/// Sends the request, retrying failures and non-2xx answers with exponential backoff.
+async fn deliver(request: reqwest::RequestBuilder, url: String) {
+    let mut backoff = INITIAL_BACKOFF;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let Some(request) = request.try_clone() else {
+            return;
+        };
+        let error = match request.send().await {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response) => format!("status {}", response.status()),
+            Err(e) => e.to_string(),
+        };
+        Log::webhook_sender_title(format!("Webhook to {} failed (attempt {}/{}): {}", url, attempt, MAX_ATTEMPTS, error).as_str());
+        if attempt < MAX_ATTEMPTS {
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+    }
+}
+
+impl Actor for WebhookSender {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(BATCH_INTERVAL, |act, _| act.flush());
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        self.flush();
+    }
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct QueueWebhook {
+    pub(crate) app_id: String,
+    pub(crate) event: WebhookEvent,
+}
+
+impl Handler<QueueWebhook> for WebhookSender {
+    type Result = ();
+
+    fn handle(&mut self, msg: QueueWebhook, _ctx: &mut Self::Context) {
+        let sends_webhook = self.app_manager
+            .find_by_id(&msg.app_id)
+            .is_ok_and(|app: AppConfig| app.sends_webhook(msg.event.name()));
+        if !sends_webhook {
+            return;
+        }
+        let events = self.pending.entry(msg.app_id.clone()).or_default();
+        events.push(msg.event);
+        if events.len() >= MAX_BATCH_SIZE {
+            self.flush_app(&msg.app_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::Instant;
+    use actix::Addr;
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use hmac::{Hmac, Mac};
+    use serde_json::Value;
+    use sha2::Sha256;
+    use crate::app::WebhookConfig;
+
+    const SECRET: &str = "app-secret";
+
+    /// A request the stub received.
+    struct Received {
+        at: Instant,
+        key: String,
+        signature: String,
+        body: String,
+    }
+
+    /// A webhook endpoint that records requests and fails the first `failures`.
+    #[derive(Default)]
+    struct Stub {
+        received: Mutex<Vec<Received>>,
+        failures: Mutex<u32>,
+    }
+
+    async fn receive(req: HttpRequest, body: String, stub: web::Data<Stub>) -> HttpResponse {
+        let header = |name: &str| req.headers().get(name).and_then(|value| value.to_str().ok()).unwrap_or_default().to_string();
+        stub.received.lock().unwrap().push(Received {
+            at: Instant::now(),
+            key: header("X-Pusher-Key"),
+            signature: header("X-Pusher-Signature"),
+            body,
+        });
+        let mut failures = stub.failures.lock().unwrap();
+        if *failures > 0 {
+            *failures -= 1;
+            return HttpResponse::InternalServerError().finish();
+        }
+        HttpResponse::Ok().finish()
+    }
+
+    /// Starts the stub and a sender for an app whose only webhook points at
+    /// it, with only `channel_occupied` webhooks enabled.
+    fn start(failures: u32) -> (web::Data<Stub>, Addr<WebhookSender>) {
+        let stub = web::Data::new(Stub {
+            failures: Mutex::new(failures),
+            ..Default::default()
+        });
+        let data = stub.clone();
+        let server = HttpServer::new(move || App::new().app_data(data.clone()).route("/webhook", web::post().to(receive)))
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let port = server.addrs()[0].port();
+        actix_web::rt::spawn(server.run());
+        let app = AppConfig {
+            id: "app".to_string(),
+            key: "app-key".to_string(),
+            secret: Some(SECRET.to_string()),
+            enabled: true,
+            has_channel_occupied_webhooks: true,
+            webhooks: vec![WebhookConfig {
+                url: format!("http://127.0.0.1:{}/webhook", port),
+                event_types: vec![],
+                filter: Default::default(),
+            }],
+            ..Default::default()
+        };
+        let sender = WebhookSender::new(Arc::new(AppManager::new(vec![app]))).start();
+        (stub, sender)
+    }
+
+    fn queue(sender: &Addr<WebhookSender>, channel: &str) {
+        sender.do_send(QueueWebhook {
+            app_id: "app".to_string(),
+            event: WebhookEvent::ChannelOccupied {
+                channel: channel.to_string(),
+            },
+        });
+    }
+
+    /// Waits up to `timeout` for the stub to receive `count` requests.
+    async fn wait_for(stub: &Stub, count: usize, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        while stub.received.lock().unwrap().len() < count && Instant::now() < deadline {
+            actix_web::rt::time::sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    fn channels(body: &str) -> Vec<String> {
+        let body: Value = serde_json::from_str(body).unwrap();
+        body["events"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|event| event["channel"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn delivers_queued_events_in_one_batch() {
+        let (stub, sender) = start(0);
+        for channel in ["a", "b", "c"] {
+            queue(&sender, channel);
+        }
+        wait_for(&stub, 1, Duration::from_secs(3)).await;
+        actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+
+        let received = stub.received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let body: Value = serde_json::from_str(&received[0].body).unwrap();
+        assert!(body["time_ms"].is_i64());
+        assert_eq!(body["events"][0]["name"], "channel_occupied");
+        assert_eq!(channels(&received[0].body), ["a", "b", "c"]);
+    }
+
+    #[actix_web::test]
+    async fn delivers_full_batches_without_waiting() {
+        let (stub, sender) = start(0);
+        let queued_at = Instant::now();
+        for i in 0..MAX_BATCH_SIZE + 1 {
+            queue(&sender, &format!("channel-{}", i));
+        }
+        wait_for(&stub, 2, Duration::from_secs(3)).await;
+
+        let received = stub.received.lock().unwrap();
+        assert_eq!(received.len(), 2);
+        assert!(received[0].at - queued_at < BATCH_INTERVAL);
+        assert_eq!(channels(&received[0].body).len(), MAX_BATCH_SIZE);
+        assert_eq!(channels(&received[1].body), [format!("channel-{}", MAX_BATCH_SIZE)]);
+    }
+
+    #[actix_web::test]
+    async fn does_not_deliver_unflagged_events() {
+        let (stub, sender) = start(0);
+        sender.do_send(QueueWebhook {
+            app_id: "app".to_string(),
+            event: WebhookEvent::MemberAdded {
+                channel: "presence-a".to_string(),
+                user_id: "1".to_string(),
+            },
+        });
+        queue(&sender, "a");
+        wait_for(&stub, 1, Duration::from_secs(3)).await;
+        actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+
+        let received = stub.received.lock().unwrap();
+        assert_eq!(received.len(), 1);
+        let body: Value = serde_json::from_str(&received[0].body).unwrap();
+        assert_eq!(body["events"].as_array().unwrap().len(), 1);
+        assert_eq!(body["events"][0]["name"], "channel_occupied");
+    }
+
+    #[actix_web::test]
+    async fn signs_the_body_with_the_app_secret() {
+        let (stub, sender) = start(0);
+        queue(&sender, "a");
+        wait_for(&stub, 1, Duration::from_secs(3)).await;
+
+        let received = stub.received.lock().unwrap();
+        let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+        mac.update(received[0].body.as_bytes());
+        assert_eq!(received[0].key, "app-key");
+        assert_eq!(received[0].signature, hex::encode(mac.finalize().into_bytes()));
+    }
+
+    #[actix_web::test]
+    async fn retries_failed_deliveries_with_backoff() {
+        let (stub, sender) = start(2);
+        queue(&sender, "a");
+        wait_for(&stub, 3, Duration::from_secs(6)).await;
+
+        let received = stub.received.lock().unwrap();
+        assert_eq!(received.len(), 3);
+        assert!(received.iter().all(|request| request.body == received[0].body));
+        assert!(received[1].at - received[0].at >= INITIAL_BACKOFF);
+        assert!(received[2].at - received[1].at >= INITIAL_BACKOFF * 2);
+    }
+
+    #[actix_web::test]
+    async fn gives_up_after_the_last_attempt() {
+        let (stub, sender) = start(u32::MAX);
+        queue(&sender, "a");
+        wait_for(&stub, MAX_ATTEMPTS as usize, Duration::from_secs(6)).await;
+        // Another attempt would come after a further doubled backoff.
+        actix_web::rt::time::sleep(INITIAL_BACKOFF * 4 + Duration::from_millis(500)).await;
+
+        assert_eq!(stub.received.lock().unwrap().len(), MAX_ATTEMPTS as usize);
+    }
+}
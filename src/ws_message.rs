@@ -1,9 +1,18 @@
-use actix::{Actor, AsyncContext, Context, Handler, Message};
-use serde_json::Value;
+use std::time::{Duration, Instant};
+use actix::{ActorContext, ActorFutureExt, AsyncContext, Handler, Message, WrapFuture};
+use bytestring::ByteString;
+use serde_json::{json, Value};
 use crate::log::Log;
-use crate::message::{PusherApiMessage, PusherMessage};
-use crate::{utils, WS};
-use crate::adapter::local_adapter::{AddSocket, AddToChannel};
+use crate::message::{MessageData, PusherMessage};
+use crate::pusher_error::PusherError;
+use crate::validation::{self, ValidationError};
+use crate::webhook::webhook_sender::QueueWebhook;
+use crate::webhook::WebhookEvent;
+use crate::WS;
+use crate::adapter::local_adapter::{AddToChannel, GetCachedEvent, JoinPresenceChannel, RemoveFromChannel, SendToChannel};
+use crate::channel_managers::{self, ChannelType};
+use crate::channel_managers::presence_channel_manager::PresenceChannelManager;
+use crate::channel_managers::private_channel_manager::PrivateChannelManager;
 
 
 #[derive(Message, Debug)]
@@ -35,47 +44,40 @@ impl Handler<OnMessage> for WS {
                 };
                 ctx.text(serde_json::to_string(&pong).unwrap());
             }
-            "pusher:ping" => {
-                let pong = PusherMessage {
-                    event: "pusher:pong".to_string(),
-                    data: None,
-                    channel: None,
-                    name: None,
-                };
-                ctx.text(serde_json::to_string(&pong).unwrap());
-            }
+            // Any frame already counts as activity for the heartbeat.
+            "pusher:pong" => {}
             "pusher:subscribe" => {
                 Log::websocket_title("Subscribing to a channel");
-                let subscription = PusherMessage {
-                    event: "pusher_internal:subscription_succeeded".to_string(),
-                    data: None,
-                    channel: None,
-                    name: None,
-                };
-                Log::websocket_title(format!("Sending subscription message: {:?}", message).as_str());
-                ctx.text(serde_json::to_string(&subscription).unwrap());
-                let message_data = message.data.unwrap();
-                self.local_adapter.do_send(AddSocket {
-                    app_id: self.app_id.clone().unwrap(),
-                    socket_id: self.id.clone().unwrap(),
-                    socket_addr: ctx.address(),
-                });
-                self.local_adapter.do_send(AddToChannel {
-                    app_id: self.app_id.clone().unwrap(),
-                    socket_id: self.id.clone().unwrap(),
-                    channel: message_data.channel.unwrap(),
-                });
+                match channel_request(&message) {
+                    Ok((channel, message_data)) => self.subscribe(channel, message_data, ctx),
+                    Err(error) => self.send_error(ctx, error),
+                }
             }
             "pusher:unsubscribe" => {
                 Log::websocket_title("Unsubscribing from a channel");
+                let channel = match channel_request(&message) {
+                    Ok((channel, _)) => channel,
+                    Err(error) => {
+                        self.send_error(ctx, error);
+                        return;
+                    }
+                };
+                if self.channels.remove(&channel).is_some() {
+                    self.local_adapter.do_send(RemoveFromChannel {
+                        app_id: self.app.id.clone(),
+                        channel: channel.clone(),
+                        socket_id: self.id.clone().unwrap(),
+                    });
+                }
                 let unsubscription = PusherMessage {
                     event: "pusher_internal:unsubscribed".to_string(),
                     data: None,
-                    channel: None,
+                    channel: Some(channel),
                     name: None,
                 };
                 ctx.text(serde_json::to_string(&unsubscription).unwrap());
             }
+            event if event.starts_with("client-") => self.client_event(message, ctx),
             _ => {
                 Log::websocket_title("Unknown event");
             }
@@ -83,16 +85,340 @@ impl Handler<OnMessage> for WS {
     }
 }
 
+/// Parses a text frame from a client into a Pusher message.
+pub(crate) fn parse_frame(text: &str) -> Result<PusherMessage, PusherError> {
+    serde_json::from_str(text).map_err(|e| PusherError::InvalidMessage(e.to_string()))
+}
+
+/// Reads the channel out of a `pusher:subscribe` or `pusher:unsubscribe`,
+/// whose `data` must be an object naming it.
+fn channel_request(message: &PusherMessage) -> Result<(String, MessageData), PusherError> {
+    let data = message
+        .data
+        .clone()
+        .and_then(|data| serde_json::from_value::<MessageData>(data).ok())
+        .ok_or_else(|| PusherError::InvalidMessage(format!("{} requires data", message.event)))?;
+    match data.channel.clone() {
+        Some(channel) => Ok((channel, data)),
+        None => Err(PusherError::InvalidMessage(format!("{} requires a channel", message.event))),
+    }
+}
+
+impl WS {
+    fn subscribe(&mut self, channel: String, message_data: MessageData, ctx: &mut <WS as actix::Actor>::Context) {
+        if self.channels.contains_key(&channel) {
+            Log::websocket_title(format!("Ignoring repeated subscription to {}", channel).as_str());
+            return;
+        }
+        if let Err(e) = validation::validate_channel_name(&self.app, &channel) {
+            let error_type = match e {
+                ValidationError::ChannelNameTooLong(_) => "LimitReached",
+                _ => "InvalidChannel",
+            };
+            Self::subscription_error(ctx, &channel, error_type, &e.to_string(), 4009);
+            return;
+        }
+        let socket_id = self.id.clone().unwrap();
+        let channel_type = ChannelType::from_name(&channel);
+        let authorized = match channel_type {
+            ChannelType::Public => true,
+            ChannelType::Private | ChannelType::PrivateEncrypted => PrivateChannelManager::signature_is_valid(
+                &self.app,
+                &socket_id,
+                &channel,
+                message_data.auth.as_deref(),
+            ),
+            ChannelType::Presence => PresenceChannelManager::signature_is_valid(
+                &self.app,
+                &socket_id,
+                &channel,
+                message_data.channel_data.as_deref().unwrap_or_default(),
+                message_data.auth.as_deref(),
+            ),
+        };
+        if !authorized {
+            Log::warning_title(format!("Unauthorized subscription to {}", channel).as_str());
+            Self::subscription_error(ctx, &channel, "AuthError", "The connection is unauthorized.", 401);
+            return;
+        }
+
+        if channel_type != ChannelType::Presence {
+            self.channels.insert(channel.clone(), None);
+            let subscription = PusherMessage {
+                event: "pusher_internal:subscription_succeeded".to_string(),
+                data: None,
+                channel: Some(channel.clone()),
+                name: None,
+            };
+            Log::websocket_title(format!("Sending subscription message: {:?}", subscription).as_str());
+            ctx.text(serde_json::to_string(&subscription).unwrap());
+            self.local_adapter.do_send(AddToChannel {
+                app_id: self.app.id.clone(),
+                socket_id,
+                channel: channel.clone(),
+            });
+            self.replay_cache(channel, ctx);
+            return;
+        }
+
+        let channel_data = message_data.channel_data.unwrap_or_default();
+        if let Some(max_size_in_kb) = self.app.max_presence_member_size_in_kb {
+            if channel_data.len() as u64 > max_size_in_kb * 1024 {
+                Self::subscription_error(
+                    ctx,
+                    &channel,
+                    "LimitReached",
+                    format!("The maximum size for a channel member is {} KB.", max_size_in_kb).as_str(),
+                    4301,
+                );
+                return;
+            }
+        }
+        let Some(member) = PresenceChannelManager::parse_member(&channel_data) else {
+            Self::subscription_error(ctx, &channel, "AuthError", "Invalid channel_data: user_id is required.", 401);
+            return;
+        };
+        self.channels.insert(channel.clone(), Some(member.user_id.clone()));
+        let join = self.local_adapter.send(JoinPresenceChannel {
+            app_id: self.app.id.clone(),
+            channel: channel.clone(),
+            socket_id,
+            member,
+        });
+        ctx.spawn(join.into_actor(self).map(move |roster, act, ctx| {
+            let Ok(Some(roster)) = roster else {
+                Log::error_title(format!("Could not join presence channel {}", channel).as_str());
+                return;
+            };
+            let ids: Vec<&String> = roster.keys().collect();
+            let subscription = json!({
+                "event": "pusher_internal:subscription_succeeded",
+                "channel": channel,
+                "data": {
+                    "presence": {
+                        "ids": ids,
+                        "hash": roster,
+                        "count": roster.len(),
+                    },
+                },
+            });
+            ctx.text(subscription.to_string());
+            act.replay_cache(channel, ctx);
+        }));
+    }
+
+    /// Sends a cache channel's last event right after `subscription_succeeded`,
+    /// or `pusher:cache_miss` when there is none.
+    fn replay_cache(&mut self, channel: String, ctx: &mut <WS as actix::Actor>::Context) {
+        if !channel_managers::is_cache_channel(&channel) {
+            return;
+        }
+        let cached = self.local_adapter.send(GetCachedEvent {
+            app_id: self.app.id.clone(),
+            channel: channel.clone(),
+        });
+        ctx.spawn(cached.into_actor(self).map(move |cached, _act, ctx| {
+            let frame = match cached {
+                Ok(Some(message)) => message,
+                Ok(None) => json!({
+                    "event": "pusher:cache_miss",
+                    "channel": channel,
+                }),
+                Err(_) => return,
+            };
+            ctx.text(frame.to_string());
+        }));
+    }
+
+    /// Forwards a `client-*` event to the other subscribers of a private or
+    /// presence channel the socket is subscribed to.
+    fn client_event(&mut self, message: PusherMessage, ctx: &mut <WS as actix::Actor>::Context) {
+        let Some(channel) = message.channel else {
+            self.send_error(ctx, PusherError::InvalidMessage("client events require a channel".to_string()));
+            return;
+        };
+        let payload_size = message.data.as_ref().map_or(0, |data| data.to_string().len());
+        let limits = validation::validate_event_name(&self.app, &message.event)
+            .and_then(|_| validation::validate_payload_size(&self.app, payload_size));
+        let rejection = if !self.app.enable_client_messages {
+            Some("The app does not have client messaging enabled.".to_string())
+        } else if let Err(e) = limits {
+            Some(e.to_string())
+        } else if ChannelType::from_name(&channel) == ChannelType::Public {
+            Some("Client events can only be sent to private and presence channels.".to_string())
+        } else if ChannelType::from_name(&channel) == ChannelType::PrivateEncrypted {
+            Some("Client events are not supported on encrypted channels.".to_string())
+        } else if !self.channels.contains_key(&channel) {
+            Some("Client events can only be sent to channels the connection is subscribed to.".to_string())
+        } else if !self.consume_client_event() {
+            Some("The rate limit for sending client events exceeded the quota.".to_string())
+        } else {
+            None
+        };
+        if let Some(reason) = rejection {
+            self.send_error(ctx, PusherError::ClientEventRejected(reason));
+            return;
+        }
+        let user_id = self.channels.get(&channel).cloned().flatten();
+        let mut event = json!({
+            "event": message.event,
+            "channel": channel,
+            "data": message.data,
+        });
+        if let Some(user_id) = &user_id {
+            event["user_id"] = json!(user_id);
+        }
+        self.local_adapter.do_send(SendToChannel {
+            app_id: self.app.id.clone(),
+            channel: channel.clone(),
+            message: event,
+            except: self.id.clone(),
+        });
+        let data = match message.data {
+            Some(Value::String(data)) => data,
+            Some(data) => data.to_string(),
+            None => String::new(),
+        };
+        self.local_adapter.do_send(QueueWebhook {
+            app_id: self.app.id.clone(),
+            event: WebhookEvent::ClientEvent {
+                channel,
+                event: message.event,
+                data,
+                socket_id: self.id.clone().unwrap_or_default(),
+                user_id,
+            },
+        });
+    }
+
+    /// Counts a client event against `max_client_events_per_second`,
+    /// returning false once the socket is over the limit.
+    fn consume_client_event(&mut self) -> bool {
+        let Some(limit) = self.app.max_client_events_per_second else {
+            return true;
+        };
+        let now = Instant::now();
+        let (window_start, count) = self.client_events.get_or_insert((now, 0));
+        if now.duration_since(*window_start) >= Duration::from_secs(1) {
+            *window_start = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count <= limit
+    }
+
+    /// Sends a `pusher:error` frame, closing the connection with the same
+    /// code when the error demands it.
+    pub(crate) fn send_error(&mut self, ctx: &mut <WS as actix::Actor>::Context, error: PusherError) {
+        Log::warning_title(format!("Socket error: {}", error).as_str());
+        ctx.text(error.to_frame().to_string());
+        if error.closes_connection() {
+            ctx.close(Some(error.close_reason()));
+            ctx.stop();
+        }
+    }
+
+    fn subscription_error(ctx: &mut <WS as actix::Actor>::Context, channel: &str, error_type: &str, error: &str, status: u16) {
+        let error = json!({
+            "event": "pusher:subscription_error",
+            "channel": channel,
+            "data": {
+                "type": error_type,
+                "error": error,
+                "status": status,
+            },
+        });
+        ctx.text(error.to_string());
+    }
+}
+
+/// An already serialized frame; cloning it only bumps a reference count, so
+/// fan-out to large channels serializes each event once.
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct OnPusherMessage {
-    pub(crate) message: Value,
+    pub(crate) message: ByteString,
 }
 
 impl Handler<OnPusherMessage> for WS {
     type Result = ();
 
     fn handle(&mut self, msg: OnPusherMessage, ctx: &mut Self::Context) -> Self::Result {
-        ctx.text(serde_json::to_string(&msg.message).unwrap());
+        ctx.text(msg.message);
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(event: &str, data: Option<Value>) -> PusherMessage {
+        PusherMessage {
+            channel: None,
+            name: None,
+            event: event.to_string(),
+            data,
+        }
+    }
+
+    #[test]
+    fn parses_well_formed_frames() {
+        let message = parse_frame(r#"{"event":"pusher:subscribe","data":{"channel":"news"}}"#).unwrap();
+        assert_eq!(message.event, "pusher:subscribe");
+        assert_eq!(message.data, Some(json!({"channel": "news"})));
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        let frames = [
+            "",
+            "not json",
+            "{",
+            "[]",
+            "null",
+            "42",
+            r#""pusher:ping""#,
+            r#"{"data":{}}"#,
+            r#"{"event":42}"#,
+            r#"{"event":null}"#,
+            r#"{"event":"pusher:ping","channel":7}"#,
+            "{\"event\":\"pusher:ping\"}\u{0}",
+            "\u{feff}{\"event\":\"pusher:ping\"}",
+        ];
+        for text in frames {
+            let error = parse_frame(text).expect_err(text);
+            assert_eq!(error.code(), 4300, "{:?}", text);
+            assert!(!error.closes_connection());
+        }
+    }
+
+    #[test]
+    fn reads_the_channel_of_subscriptions() {
+        for event in ["pusher:subscribe", "pusher:unsubscribe"] {
+            let data = json!({"channel": "private-news", "auth": "key:signature"});
+            let (channel, message_data) = channel_request(&frame(event, Some(data))).unwrap();
+            assert_eq!(channel, "private-news");
+            assert_eq!(message_data.auth.as_deref(), Some("key:signature"));
+        }
+    }
+
+    #[test]
+    fn rejects_subscriptions_without_a_channel() {
+        let cases = [
+            (None, "requires data"),
+            (Some(Value::Null), "requires data"),
+            (Some(json!("news")), "requires data"),
+            (Some(json!(["news"])), "requires data"),
+            (Some(json!(7)), "requires data"),
+            (Some(json!({"channel": 7})), "requires data"),
+            (Some(json!({})), "requires a channel"),
+            (Some(json!({"channel": null})), "requires a channel"),
+            (Some(json!({"auth": "key:signature"})), "requires a channel"),
+        ];
+        for event in ["pusher:subscribe", "pusher:unsubscribe"] {
+            for (data, reason) in &cases {
+                let error = channel_request(&frame(event, data.clone())).expect_err(event);
+                assert_eq!(error, PusherError::InvalidMessage(format!("{} {}", event, reason)), "{:?}", data);
+            }
+        }
+    }
+}